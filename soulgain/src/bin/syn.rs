@@ -1,28 +1,142 @@
-use rand::Rng;
-use soulgain::evolution::Trainer;
-use soulgain::types::UVal;
-use soulgain::vm::Op;
+use rand::{Rng, RngCore};
+use soulgain::budget::{Progress, SearchEvent};
+use soulgain::evolution::{Oracle, SynthesisReport, Trainer};
+use soulgain::types::{SkillLibrary, UVal};
 use soulgain::SoulGainVM;
+use std::time::Duration;
 
 // --- CONSTANTS FOR PERSISTENCE ---
-const SKILLS_PATH: &str = "skills.json";
-const PLASTICITY_PATH: &str = "plasticity.json";
+const BRAIN_DIR: &str = "syn_brain";
+// Pre-bundle layout, only read when no bundle exists yet
+const LEGACY_SKILLS_PATH: &str = "skills.json";
+const LEGACY_PLASTICITY_PATH: &str = "plasticity.json";
 const ATTEMPTS_LIMIT: usize = 100_000; // Increased to 100k as requested
+const TIME_LIMIT: Duration = Duration::from_secs(90);
 
-fn random_examples(n: usize, mul: bool) -> Vec<(Vec<UVal>, Vec<UVal>)> {
-    let mut rng = rand::thread_rng();
-    (0..n)
-        .map(|_| {
-            let a = rng.gen_range(1..25) as f64;
-            let b = rng.gen_range(1..25) as f64;
-            let m = [3.0, 5.0, 7.0, 11.0][rng.gen_range(0..4)];
-            let out = if mul { (a * b) % m } else { (a + b) % m };
-            (
-                vec![UVal::Number(a), UVal::Number(b), UVal::Number(m)],
-                vec![UVal::Number(out)],
-            )
-        })
-        .collect()
+/// (a op b) % m, the reference for counterexample-guided synthesis.
+struct ModArithmetic {
+    mul: bool,
+}
+
+impl Oracle for ModArithmetic {
+    fn evaluate(&self, input: Vec<UVal>) -> Vec<UVal> {
+        match input.as_slice() {
+            [UVal::Number(a), UVal::Number(b), UVal::Number(m)] => {
+                let out = if self.mul { (a * b) % m } else { (a + b) % m };
+                vec![UVal::Number(out)]
+            }
+            _ => vec![],
+        }
+    }
+
+    fn generate_input(&self, rng: &mut dyn RngCore) -> Option<Vec<UVal>> {
+        let a = rng.gen_range(1..25) as f64;
+        let b = rng.gen_range(1..25) as f64;
+        let m = [3.0, 5.0, 7.0, 11.0][rng.gen_range(0..4)];
+        Some(vec![UVal::Number(a), UVal::Number(b), UVal::Number(m)])
+    }
+}
+
+struct EvenOdd;
+
+impl Oracle for EvenOdd {
+    fn evaluate(&self, input: Vec<UVal>) -> Vec<UVal> {
+        let n = if let Some(UVal::Number(num)) = input.first() { *num } else { 0.0 };
+        vec![UVal::Bool((n as i64) % 2 == 0)]
+    }
+
+    fn generate_input(&self, rng: &mut dyn RngCore) -> Option<Vec<UVal>> {
+        Some(vec![UVal::Number(rng.gen_range(0..100) as f64)])
+    }
+}
+
+fn print_event(event: &SearchEvent) {
+    match event {
+        SearchEvent::Rejected { solved, failed, validation } => println!(
+            "  [REJECTED] Solves {} examples but fails {} of {} held-out ones",
+            solved, failed, validation
+        ),
+        SearchEvent::Enumerated(stats) => println!(
+            "  [ENUM] {} candidates, {} distinct behaviours, depth {}",
+            stats.evaluated, stats.distinct, stats.depth
+        ),
+        SearchEvent::Evolved { generations, solved: true } => {
+            println!("  [GP] Solved in generation {}", generations)
+        }
+        SearchEvent::Evolved { generations, solved: false } => {
+            println!("  [GP] No solution after {} generations", generations)
+        }
+        SearchEvent::Promoted { skill, len, before, after } => {
+            if before > after {
+                println!("  [OPTIMIZED] Len {} -> Len {}", before, after);
+            }
+            println!("  [SUCCESS] Concept: Opcode {} | Len: {}", skill, len);
+        }
+        SearchEvent::Counterexample { round, input, expected } => println!(
+            "  [CEGIS] Round {}: counterexample {:?} -> {:?}",
+            round, input, expected
+        ),
+    }
+}
+
+/// Prints events and each new best of one run. A counterexample changes
+/// the examples, so the best so far starts over.
+fn progress_printer() -> impl FnMut(&Progress) + Send {
+    let mut best = 0.0;
+    move |p: &Progress| {
+        if let Some(event) = &p.event {
+            if matches!(event, SearchEvent::Counterexample { .. }) {
+                best = 0.0;
+            }
+            print_event(event);
+            return;
+        }
+        if p.best_fitness > best {
+            best = p.best_fitness;
+            println!(
+                "  ... best {:.4} (len {}, {}) after {} candidates, {:.1}s",
+                p.best_fitness,
+                p.best_len,
+                p.strategy.as_deref().unwrap_or("?"),
+                p.evaluations,
+                p.elapsed.as_secs_f64()
+            );
+        }
+    }
+}
+
+fn print_report(name: &str, report: &SynthesisReport) {
+    let validation = report
+        .validation_accuracy
+        .map_or("n/a".to_string(), |acc| format!("{:.1}%", acc * 100.0));
+    println!(
+        "  {}: train {:.1}%, validation {} ({} candidates)",
+        name,
+        report.train_accuracy * 100.0,
+        validation,
+        report.evaluations
+    );
+    if let Some(reason) = report.stopped {
+        println!("  Stopped early: {:?}", reason);
+    }
+    match (&report.program, report.generalizes()) {
+        (Some(program), true) => println!(
+            "✓ Synthesized {} via {}: {:?}",
+            name,
+            report.strategy.as_deref().unwrap_or("?"),
+            program
+        ),
+        (Some(program), false) => {
+            println!("✗ {} did not generalize: {:?}", name, program);
+            for failure in report.failures.iter().take(3) {
+                println!(
+                    "    {:?} -> expected {:?}, got {:?}",
+                    failure.input, failure.expected, failure.got
+                );
+            }
+        }
+        (None, _) => println!("✗ No candidate for {}", name),
+    }
 }
 
 fn print_separator(title: &str) {
@@ -37,48 +151,73 @@ fn main() {
     // Initialize VM and try to load existing state
     let mut vm = SoulGainVM::new(vec![]);
     
-    if let Ok(_) = vm.plasticity.load_from_file(PLASTICITY_PATH) {
-        println!("✓ Loaded existing plasticity from {}", PLASTICITY_PATH);
+    match vm.load_brain(BRAIN_DIR) {
+        Ok(manifest) => println!(
+            "✓ Resumed brain from {} (tick {}, {} skills)",
+            BRAIN_DIR,
+            manifest.counters.tick,
+            vm.skills.macros.len()
+        ),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            if vm.plasticity.load_from_file(LEGACY_PLASTICITY_PATH).is_ok() {
+                println!("✓ Migrated plasticity from {}", LEGACY_PLASTICITY_PATH);
+            }
+            if let Ok(skills) = SkillLibrary::load_from_file(LEGACY_SKILLS_PATH) {
+                vm.skills = skills;
+                println!("✓ Migrated skills from {}", LEGACY_SKILLS_PATH);
+            }
+        }
+        Err(err) => {
+            eprintln!("✗ Brain bundle {} could not be loaded: {}", BRAIN_DIR, err);
+            std::process::exit(1);
+        }
     }
-    
-    // Note: SkillLibrary currently lacks a built-in load_from_file in the provided snippet,
-    // but the Trainer will populate new skills into the VM's registry.
 
-    let mut trainer = Trainer::new(vm, 15); // Increased max program length for complexity
+    let mut trainer = Trainer::new(vm, 15) // Increased max program length for complexity
+        .with_time_limit(TIME_LIMIT)
+        .with_workers(std::thread::available_parallelism().map_or(1, |n| n.get()))
+        .with_progress(progress_printer());
 
     // --- TEST 1: MODULAR ARITHMETIC ---
     println!("\n[Task 1] Addition Modulo (Attempts: {})", ATTEMPTS_LIMIT);
-    let add_examples = random_examples(5, false);
-    if let Some(program) = trainer.synthesize(&add_examples, ATTEMPTS_LIMIT) {
-        println!("✓ Synthesized AddMod: {:?}", program);
-    }
+    let report = trainer.synthesize_with_oracle(&ModArithmetic { mul: false }, ATTEMPTS_LIMIT);
+    print_report("AddMod", &report);
 
     // --- TEST 2: EVEN/ODD LOGIC ---
     println!("\n[Task 2] Even/Odd Detection (Attempts: {})", ATTEMPTS_LIMIT);
-    let even_inputs = vec![vec![UVal::Number(4.0)], vec![UVal::Number(7.0)], vec![UVal::Number(12.0)]];
-    let even_examples: Vec<(Vec<UVal>, Vec<UVal>)> = even_inputs.iter().map(|input| {
-        let n = if let Some(UVal::Number(num)) = input.first() { *num } else { 0.0 };
-        (input.clone(), vec![UVal::Bool((n as i64) % 2 == 0)])
-    }).collect();
+    trainer = trainer.with_progress(progress_printer());
+    let report = trainer.synthesize_with_oracle(&EvenOdd, ATTEMPTS_LIMIT);
+    print_report("Even/Odd", &report);
 
-    if let Some(program) = trainer.synthesize(&even_examples, ATTEMPTS_LIMIT) {
-        println!("✓ Synthesized Even/Odd: {:?}", program);
+    for (name, stats) in trainer.strategies().stats() {
+        println!(
+            "  {:<10} proposals {:>7} | improvements {:>4} | solutions {}",
+            name, stats.proposals, stats.improvements, stats.solutions
+        );
     }
 
     // --- PERSISTENCE BLOCK ---
     print_separator("SAVING BRAIN STATE");
     
-    // Save Plasticity weights
-    match trainer.vm.plasticity.save_to_file(PLASTICITY_PATH) {
-        Ok(_) => println!("✓ Plasticity saved to {}", PLASTICITY_PATH),
-        Err(e) => println!("✗ Plasticity save failed: {}", e),
+    let merged: usize = trainer
+        .vm
+        .deduplicate_skills()
+        .iter()
+        .map(|group| group.duplicates.len())
+        .sum();
+    if merged > 0 {
+        println!("✓ Merged {} behaviorally duplicate skills", merged);
+    }
+    let roots = trainer.vm.skills.root_skills();
+    let removed = trainer.vm.collect_skill_garbage(&roots);
+    if !removed.is_empty() {
+        println!("✓ Collected {} orphaned speculative skills", removed.len());
     }
 
-    // To save skills.json, we manually serialize the SkillLibrary
-    let skills_file = std::fs::File::create(SKILLS_PATH).expect("Failed to create skills file");
-    if let Ok(_) = serde_json::to_writer_pretty(skills_file, &trainer.vm.skills) {
-        println!("✓ Skills saved to {}", SKILLS_PATH);
+    match trainer.vm.save_brain(BRAIN_DIR) {
+        Ok(_) => println!("✓ Brain saved to {}", BRAIN_DIR),
+        Err(e) => println!("✗ Brain save failed: {}", e),
     }
 
-    println!("\nRun complete. Check {} and {} for persisted data.", SKILLS_PATH, PLASTICITY_PATH);
+    println!("\nRun complete. Check {} for persisted data.", BRAIN_DIR);
 }
\ No newline at end of file
@@ -0,0 +1,153 @@
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::persistence::{self, SaveOptions};
+
+/// Identifies a directory written by `SoulGainVM::save_brain`.
+pub const BRAIN_BUNDLE_FORMAT: &str = "soulgain-brain";
+/// Bumped whenever a component file changes incompatibly.
+///
+/// 2: `intuition.json` wraps the engine in a versioned envelope. Version 1
+/// bundles still load.
+pub const BRAIN_BUNDLE_VERSION: u32 = 2;
+
+pub const MANIFEST_FILE: &str = "manifest.json";
+pub const PLASTICITY_FILE: &str = "plasticity.sgb";
+pub const SKILLS_FILE: &str = "skills.json";
+pub const INTUITION_FILE: &str = "intuition.json";
+pub const MEMORY_FILE: &str = "memory.json";
+
+/// Written last. Bundles are assembled in a staging directory and only then
+/// moved into place, so a bundle directory never mixes components of two
+/// saves.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BrainManifest {
+    pub format: String,
+    pub version: u32,
+    pub crate_version: String,
+    pub saved_at: u64,
+    pub counters: VmCounters,
+}
+
+/// Execution counters the intuition layer measures ages and rewards against.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct VmCounters {
+    pub tick: u64,
+    pub total_reward: f64,
+    pub error_count: u64,
+}
+
+impl BrainManifest {
+    pub fn new(counters: VmCounters) -> Self {
+        Self {
+            format: BRAIN_BUNDLE_FORMAT.to_string(),
+            version: BRAIN_BUNDLE_VERSION,
+            crate_version: env!("CARGO_PKG_VERSION").to_string(),
+            saved_at: unix_now(),
+            counters,
+        }
+    }
+
+    pub fn check_compatible(&self) -> io::Result<()> {
+        if self.format != BRAIN_BUNDLE_FORMAT {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("not a brain bundle (format `{}`)", self.format),
+            ));
+        }
+        if self.version > BRAIN_BUNDLE_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "brain bundle version {} is newer than supported version {}",
+                    self.version, BRAIN_BUNDLE_VERSION
+                ),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// A fresh, empty directory next to `dir` to assemble a bundle in.
+pub(crate) fn staging_dir(dir: &Path) -> io::Result<PathBuf> {
+    if let Some(parent) = dir.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+    let staging = persistence::temp_path(dir);
+    fs::create_dir(&staging)?;
+    Ok(staging)
+}
+
+/// Moves a complete bundle from `staging` to `dir`. The bundle it replaces
+/// becomes `dir.bak1`, which `bundle_dir` falls back to if a crash between
+/// the two renames leaves no `dir`.
+pub(crate) fn commit_staged(staging: &Path, dir: &Path) -> io::Result<()> {
+    let backup = persistence::backup_path(dir, 1);
+    if dir.exists() {
+        if backup.exists() {
+            fs::remove_dir_all(&backup)?;
+        }
+        fs::rename(dir, &backup)?;
+    }
+    fs::rename(staging, dir)?;
+    if let Some(parent) = dir.parent().filter(|p| !p.as_os_str().is_empty())
+        && let Ok(parent) = fs::File::open(parent)
+    {
+        let _ = parent.sync_all();
+    }
+    Ok(())
+}
+
+/// `dir`, or its backup if only the backup holds a bundle.
+pub(crate) fn bundle_dir(dir: &Path) -> PathBuf {
+    let backup = persistence::backup_path(dir, 1);
+    if !dir.join(MANIFEST_FILE).exists() && backup.join(MANIFEST_FILE).exists() {
+        backup
+    } else {
+        dir.to_path_buf()
+    }
+}
+
+pub(crate) fn read_manifest(dir: &Path) -> io::Result<BrainManifest> {
+    let manifest: BrainManifest = read_json(&dir.join(MANIFEST_FILE))?;
+    manifest.check_compatible()?;
+    Ok(manifest)
+}
+
+pub(crate) fn write_json<T: Serialize + DeserializeOwned>(
+    path: &Path,
+    value: &T,
+) -> io::Result<()> {
+    let bytes = persistence::to_json_checked::<T>(value)?;
+    persistence::write_atomic(path, &bytes, &bundle_save_options())
+}
+
+pub(crate) fn read_json<T: DeserializeOwned>(path: &Path) -> io::Result<T> {
+    let bytes = fs::read(path)?;
+    serde_json::from_slice(&bytes).map_err(|err| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: {}", path.display(), err),
+        )
+    })
+}
+
+/// Components go into a fresh staging directory, so they need no backups
+/// of their own; the whole previous bundle is kept instead.
+pub(crate) fn bundle_save_options() -> SaveOptions {
+    SaveOptions {
+        backups: 0,
+        ..SaveOptions::default()
+    }
+}
+
+pub(crate) fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
@@ -0,0 +1,229 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::enumerate::EnumerationStats;
+use crate::types::UVal;
+
+/// Evaluations between two progress callbacks (improvements are always
+/// reported immediately).
+pub const PROGRESS_INTERVAL: usize = 1_000;
+
+/// Stops a running synthesis from another thread. Clones share one flag.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Clears the flag so the token can be reused for the next run.
+    pub fn reset(&self) {
+        self.0.store(false, Ordering::Relaxed);
+    }
+}
+
+/// Why a synthesis run stopped before exhausting its search space.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    Deadline,
+    Cancelled,
+}
+
+/// Snapshot handed to the progress callback.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Progress {
+    /// Candidates evaluated so far in this run.
+    pub evaluations: usize,
+    pub elapsed: Duration,
+    pub best_fitness: f64,
+    /// Length (in words) of the best program so far.
+    pub best_len: usize,
+    /// Length the search is currently working at.
+    pub target_len: usize,
+    /// Strategy (or search mode) that produced the best program.
+    pub strategy: Option<String>,
+    /// Set when the callback is reporting an event rather than progress.
+    pub event: Option<SearchEvent>,
+}
+
+/// Something that happened during a search, reported through the progress
+/// callback so callers decide what to print.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SearchEvent {
+    /// A candidate solved all `solved` training examples but failed `failed`
+    /// of the `validation` held-out ones.
+    Rejected {
+        solved: usize,
+        failed: usize,
+        validation: usize,
+    },
+    /// An enumerative search finished.
+    Enumerated(EnumerationStats),
+    /// A genetic search finished after `generations` generations, with a
+    /// solution if `solved`.
+    Evolved { generations: usize, solved: bool },
+    /// A solution was promoted to `skill`. `len` is its length in
+    /// instructions; `before` and `after` are the words in its logic before
+    /// and after pruning.
+    Promoted {
+        skill: i64,
+        len: usize,
+        before: usize,
+        after: usize,
+    },
+    /// Counterexample-guided synthesis found a candidate that disagrees
+    /// with the oracle on `input`; it joins the examples for round `round`.
+    Counterexample {
+        round: usize,
+        input: Vec<UVal>,
+        expected: Vec<UVal>,
+    },
+}
+
+pub type ProgressCallback = Box<dyn FnMut(&Progress) + Send>;
+
+/// Per-run bookkeeping for deadlines, cancellation and progress.
+pub(crate) struct Monitor {
+    started: Instant,
+    /// Evaluations made before this monitor took over the run.
+    base: usize,
+    deadline: Option<Instant>,
+    token: CancellationToken,
+    callback: Option<ProgressCallback>,
+    progress: Progress,
+    stopped: Option<StopReason>,
+}
+
+impl Monitor {
+    pub(crate) fn new(
+        time_limit: Option<Duration>,
+        token: CancellationToken,
+        callback: Option<ProgressCallback>,
+    ) -> Self {
+        let started = Instant::now();
+        Self {
+            started,
+            base: 0,
+            deadline: time_limit.map(|limit| started + limit),
+            token,
+            callback,
+            progress: Progress {
+                evaluations: 0,
+                elapsed: Duration::ZERO,
+                best_fitness: 0.0,
+                best_len: 0,
+                target_len: 0,
+                strategy: None,
+                event: None,
+            },
+            stopped: None,
+        }
+    }
+
+    /// Counts one evaluation at `target_len`. Returns false once the run
+    /// has to stop; the reason is kept for the report.
+    pub(crate) fn tick(&mut self, target_len: usize) -> bool {
+        self.progress.target_len = target_len;
+        self.charge()
+    }
+
+    /// Counts one evaluation made on behalf of the current candidate, such
+    /// as a trial filling of its constants. Returns false once the run has
+    /// to stop.
+    pub(crate) fn charge(&mut self) -> bool {
+        if self.stopped.is_some() {
+            return false;
+        }
+        if self.token.is_cancelled() {
+            self.stopped = Some(StopReason::Cancelled);
+            return false;
+        }
+        if self
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            self.stopped = Some(StopReason::Deadline);
+            return false;
+        }
+        self.progress.evaluations += 1;
+        if self.progress.evaluations.is_multiple_of(PROGRESS_INTERVAL) {
+            self.notify();
+        }
+        true
+    }
+
+    /// Records a new best candidate and reports it.
+    pub(crate) fn improve(&mut self, fitness: f64, len: usize, strategy: &str) {
+        self.progress.best_fitness = fitness;
+        self.progress.best_len = len;
+        self.progress.strategy = Some(strategy.to_string());
+        self.notify();
+    }
+
+    /// Hands `event` to the callback.
+    pub(crate) fn report(&mut self, event: SearchEvent) {
+        self.progress.event = Some(event);
+        self.notify();
+        self.progress.event = None;
+    }
+
+    /// Carries on a run that began at `started` and has already evaluated
+    /// `evaluations` candidates: reported progress counts from there.
+    pub(crate) fn resume(mut self, started: Instant, evaluations: usize) -> Self {
+        self.started = started;
+        self.base = evaluations;
+        self
+    }
+
+    fn notify(&mut self) {
+        if let Some(callback) = self.callback.as_mut() {
+            self.progress.elapsed = self.started.elapsed();
+            self.progress.evaluations += self.base;
+            callback(&self.progress);
+            self.progress.evaluations -= self.base;
+        }
+    }
+
+    /// Counts `evaluations` made by a search run on this monitor's behalf.
+    pub(crate) fn absorb(&mut self, evaluations: usize) {
+        self.progress.evaluations += evaluations;
+    }
+
+    /// Time left before the deadline, if there is one.
+    pub(crate) fn remaining(&self) -> Option<Duration> {
+        self.deadline
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    /// Lends the callback to a nested search; `give_callback` returns it.
+    pub(crate) fn take_callback(&mut self) -> Option<ProgressCallback> {
+        self.callback.take()
+    }
+
+    pub(crate) fn give_callback(&mut self, callback: Option<ProgressCallback>) {
+        self.callback = callback;
+    }
+
+    pub(crate) fn evaluations(&self) -> usize {
+        self.progress.evaluations
+    }
+
+    pub(crate) fn stopped(&self) -> Option<StopReason> {
+        self.stopped
+    }
+
+    /// Hands the callback back to its owner once the run is over.
+    pub(crate) fn into_callback(self) -> Option<ProgressCallback> {
+        self.callback
+    }
+}
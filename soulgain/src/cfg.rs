@@ -0,0 +1,303 @@
+use std::collections::{HashMap, VecDeque};
+use std::ops::Range;
+
+use crate::logic::{LogicValidationError, StackEffect, decode_opcodes, has_operand, jump_target};
+use crate::types::SkillLibrary;
+use crate::vm::{Op, SKILL_OPCODE_BASE};
+
+/// One decoded instruction of an encoded program.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Instruction {
+    /// Index of the opcode word in the program.
+    pub index: usize,
+    pub opcode: i64,
+    pub operand: Option<f64>,
+}
+
+impl Instruction {
+    pub fn op(&self) -> Option<Op> {
+        Op::from_i64(self.opcode)
+    }
+
+    pub fn is_skill(&self) -> bool {
+        self.opcode >= SKILL_OPCODE_BASE
+    }
+
+    /// Index of the first word after this instruction.
+    pub fn end(&self) -> usize {
+        self.index + 1 + self.operand.is_some() as usize
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Successor {
+    /// Position in `ControlFlowGraph::instructions`.
+    Instr(usize),
+    /// `Halt`, or running off the end of the program.
+    Exit,
+}
+
+#[derive(Debug, Clone)]
+pub struct ControlFlowGraph {
+    pub instructions: Vec<Instruction>,
+    successors: Vec<Vec<Successor>>,
+}
+
+/// Result of a successful `analyze_program`.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ProgramAnalysis {
+    /// Word ranges no execution path can reach.
+    pub unreachable: Vec<Range<usize>>,
+    /// Whether some path executes a `Halt` (rather than only running off the end).
+    pub reaches_halt: bool,
+    /// Smallest stack depth seen at any reachable exit.
+    pub min_exit_depth: Option<usize>,
+}
+
+impl ControlFlowGraph {
+    /// Decodes `program` and resolves every jump, call and return edge.
+    pub fn build(program: &[f64]) -> Result<Self, LogicValidationError> {
+        let decoded = decode_opcodes(program)?;
+        let position: HashMap<usize, usize> = decoded
+            .iter()
+            .enumerate()
+            .map(|(pos, (index, _))| (*index, pos))
+            .collect();
+
+        let mut instructions = Vec::with_capacity(decoded.len());
+        for (index, opcode) in decoded {
+            let op = Op::from_i64(opcode);
+            if opcode < SKILL_OPCODE_BASE && op.is_none() {
+                return Err(LogicValidationError::InvalidOpcode(opcode));
+            }
+            instructions.push(Instruction {
+                index,
+                opcode,
+                operand: op.filter(|op| has_operand(*op)).map(|_| program[index + 1]),
+            });
+        }
+
+        let resolve = |instr: &Instruction| -> Result<usize, LogicValidationError> {
+            let op = instr.op().expect("only jumps are resolved");
+            let target = instr
+                .operand
+                .and_then(|operand| jump_target(op, instr.index, operand))
+                .unwrap_or(-1);
+            if target < 0 || target as usize >= program.len() {
+                return Err(LogicValidationError::InvalidJumpTarget {
+                    index: instr.index,
+                    target,
+                });
+            }
+            position
+                .get(&(target as usize))
+                .copied()
+                .ok_or(LogicValidationError::JumpIntoOperand {
+                    index: instr.index,
+                    target: target as usize,
+                })
+        };
+
+        let next = |pos: usize| {
+            if pos + 1 < instructions.len() {
+                Successor::Instr(pos + 1)
+            } else {
+                Successor::Exit
+            }
+        };
+        let return_sites: Vec<Successor> = instructions
+            .iter()
+            .enumerate()
+            .filter(|(_, instr)| matches!(instr.op(), Some(Op::Call | Op::CallRel)))
+            .map(|(pos, _)| next(pos))
+            .collect();
+
+        let mut successors = Vec::with_capacity(instructions.len());
+        for (pos, instr) in instructions.iter().enumerate() {
+            let edges = match instr.op() {
+                Some(Op::Halt) => vec![Successor::Exit],
+                Some(Op::Jmp | Op::JmpRel | Op::Call | Op::CallRel) => {
+                    vec![Successor::Instr(resolve(instr)?)]
+                }
+                Some(Op::JmpIf | Op::JmpIfRel) => {
+                    vec![Successor::Instr(resolve(instr)?), next(pos)]
+                }
+                Some(Op::Ret) => {
+                    if return_sites.is_empty() {
+                        return Err(LogicValidationError::ReturnWithoutCall { index: instr.index });
+                    }
+                    return_sites.clone()
+                }
+                _ => vec![next(pos)],
+            };
+            successors.push(edges);
+        }
+
+        Ok(Self {
+            instructions,
+            successors,
+        })
+    }
+
+    pub fn successors(&self, pos: usize) -> &[Successor] {
+        &self.successors[pos]
+    }
+
+    /// Positions reachable from the entry instruction.
+    pub fn reachable(&self) -> Vec<bool> {
+        let mut seen = vec![false; self.instructions.len()];
+        let mut queue = VecDeque::new();
+        if !self.instructions.is_empty() {
+            seen[0] = true;
+            queue.push_back(0);
+        }
+        while let Some(pos) = queue.pop_front() {
+            for succ in &self.successors[pos] {
+                if let Successor::Instr(next) = *succ
+                    && !seen[next]
+                {
+                    seen[next] = true;
+                    queue.push_back(next);
+                }
+            }
+        }
+        seen
+    }
+
+    /// Positions from which some path reaches an exit.
+    pub fn can_exit(&self) -> Vec<bool> {
+        let mut predecessors: Vec<Vec<usize>> = vec![Vec::new(); self.instructions.len()];
+        let mut queue = VecDeque::new();
+        let mut exits = vec![false; self.instructions.len()];
+        for (pos, succs) in self.successors.iter().enumerate() {
+            for succ in succs {
+                match *succ {
+                    Successor::Instr(next) => predecessors[next].push(pos),
+                    Successor::Exit => {
+                        if !exits[pos] {
+                            exits[pos] = true;
+                            queue.push_back(pos);
+                        }
+                    }
+                }
+            }
+        }
+        while let Some(pos) = queue.pop_front() {
+            for &pred in &predecessors[pos] {
+                if !exits[pred] {
+                    exits[pred] = true;
+                    queue.push_back(pred);
+                }
+            }
+        }
+        exits
+    }
+}
+
+/// Builds the control-flow graph of `program` and checks it along every path
+/// starting from a stack of `initial_depth` values: jump targets must land on
+/// instruction boundaries, no path may underflow the stack (skill calls use
+/// their inferred stack effect) and every reachable instruction must be able
+/// to reach an exit.
+pub fn analyze_program(
+    program: &[f64],
+    skills: &SkillLibrary,
+    initial_depth: usize,
+) -> Result<ProgramAnalysis, LogicValidationError> {
+    let cfg = ControlFlowGraph::build(program)?;
+    analyze_graph(program, &cfg, skills, initial_depth)
+}
+
+/// `analyze_program` for a graph already built from `program`.
+pub fn analyze_graph(
+    program: &[f64],
+    cfg: &ControlFlowGraph,
+    skills: &SkillLibrary,
+    initial_depth: usize,
+) -> Result<ProgramAnalysis, LogicValidationError> {
+    let mut analysis = ProgramAnalysis::default();
+    if cfg.instructions.is_empty() {
+        analysis.min_exit_depth = Some(initial_depth);
+        return Ok(analysis);
+    }
+
+    let reachable = cfg.reachable();
+    let can_exit = cfg.can_exit();
+    if let Some(pos) = (0..cfg.instructions.len()).find(|&pos| reachable[pos] && !can_exit[pos]) {
+        return Err(LogicValidationError::NoExit {
+            index: cfg.instructions[pos].index,
+        });
+    }
+
+    let mut effects = Vec::with_capacity(cfg.instructions.len());
+    for (pos, instr) in cfg.instructions.iter().enumerate() {
+        let effect = if !reachable[pos] {
+            StackEffect::default()
+        } else if instr.is_skill() {
+            skills.try_stack_effect(instr.opcode)?
+        } else {
+            StackEffect::of_op(instr.op().expect("decoded during build"))
+        };
+        effects.push(effect);
+    }
+
+    // Track the smallest depth that can reach each instruction. Depths only
+    // ever decrease and are bounded below, so the worklist terminates.
+    let mut min_depth: Vec<Option<usize>> = vec![None; cfg.instructions.len()];
+    let mut queue = VecDeque::from([0usize]);
+    min_depth[0] = Some(initial_depth);
+    while let Some(pos) = queue.pop_front() {
+        let instr = &cfg.instructions[pos];
+        let depth = min_depth[pos].expect("queued positions have a depth");
+        let effect = effects[pos];
+        if depth < effect.inputs {
+            return Err(match instr.op() {
+                Some(op) => LogicValidationError::StackUnderflow {
+                    index: instr.index,
+                    op,
+                    needed: effect.inputs,
+                    available: depth,
+                },
+                None => LogicValidationError::SkillUnderflow {
+                    index: instr.index,
+                    skill: instr.opcode,
+                    needed: effect.inputs,
+                    available: depth,
+                },
+            });
+        }
+        let out = depth - effect.inputs + effect.outputs;
+        for succ in cfg.successors(pos) {
+            match *succ {
+                Successor::Instr(next) => {
+                    if min_depth[next].is_none_or(|d| out < d) {
+                        min_depth[next] = Some(out);
+                        queue.push_back(next);
+                    }
+                }
+                Successor::Exit => {
+                    analysis.reaches_halt |= instr.op() == Some(Op::Halt);
+                    analysis.min_exit_depth =
+                        Some(analysis.min_exit_depth.map_or(out, |d| d.min(out)));
+                }
+            }
+        }
+    }
+
+    let mut start: Option<usize> = None;
+    for (pos, instr) in cfg.instructions.iter().enumerate() {
+        match (reachable[pos], start) {
+            (false, None) => start = Some(instr.index),
+            (true, Some(from)) => {
+                analysis.unreachable.push(from..instr.index);
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(from) = start {
+        analysis.unreachable.push(from..program.len());
+    }
+
+    Ok(analysis)
+}
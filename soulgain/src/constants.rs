@@ -0,0 +1,153 @@
+use crate::logic::instruction_starts;
+use crate::types::UVal;
+use crate::vm::Op;
+
+/// Operand of a `Literal` whose value is still unknown. Search strategies
+/// emit `Literal HOLE` and the trainer solves for the value from the
+/// examples before the program is scored.
+pub const HOLE: f64 = f64::NAN;
+
+/// Most holes one program may have for the linear fit.
+pub const MAX_FIT_HOLES: usize = 3;
+
+/// Most holes one program may have for small-integer enumeration, which
+/// runs the program once per assignment.
+pub const MAX_ENUM_HOLES: usize = 1;
+
+/// Largest magnitude tried when enumerating hole values.
+pub const ENUM_RANGE: i64 = 10;
+
+pub fn is_hole(word: f64) -> bool {
+    word.is_nan()
+}
+
+/// Indices of the operand words of every `Literal` that is still a hole.
+pub fn holes(program: &[f64]) -> Vec<usize> {
+    instruction_starts(program)
+        .into_iter()
+        .filter(|&idx| program[idx] == Op::Literal.as_f64())
+        .map(|idx| idx + 1)
+        .filter(|&idx| program.get(idx).is_some_and(|word| is_hole(*word)))
+        .collect()
+}
+
+/// Turns every `Literal` operand of `program` back into a hole.
+pub fn reopen(program: &mut [f64]) {
+    for idx in instruction_starts(program) {
+        if program[idx] == Op::Literal.as_f64() && idx + 1 < program.len() {
+            program[idx + 1] = HOLE;
+        }
+    }
+}
+
+/// Copy of `program` with `holes[i]` set to `values[i]`.
+pub fn fill(program: &[f64], holes: &[usize], values: &[f64]) -> Vec<f64> {
+    let mut filled = program.to_vec();
+    for (&idx, &value) in holes.iter().zip(values) {
+        filled[idx] = value;
+    }
+    filled
+}
+
+/// Small integers by increasing magnitude: 0, 1, -1, 2, -2, ...
+pub fn small_integers() -> impl Iterator<Item = f64> {
+    std::iter::once(0.0).chain((1..=ENUM_RANGE).flat_map(|n| [n as f64, -n as f64]))
+}
+
+/// Every assignment of `small_integers` to `count` holes, the small ones
+/// first.
+pub fn assignments(count: usize) -> Vec<Vec<f64>> {
+    let values: Vec<f64> = small_integers().collect();
+    let mut assignments = vec![Vec::new()];
+    for _ in 0..count {
+        assignments = assignments
+            .into_iter()
+            .flat_map(|prefix| {
+                values.iter().map(move |&v| {
+                    let mut next = prefix.clone();
+                    next.push(v);
+                    next
+                })
+            })
+            .collect();
+    }
+    assignments.sort_by(|a, b| {
+        let size = |xs: &[f64]| xs.iter().map(|x| x.abs()).sum::<f64>();
+        size(a).total_cmp(&size(b))
+    });
+    assignments
+}
+
+/// Fits hole values assuming each output number is affine in them.
+///
+/// `probes[0]` holds the final stacks with every hole at zero and
+/// `probes[j + 1]` those with only hole `j` at one, each indexed like
+/// `expected`. Every numeric slot of an expected stack gives one equation;
+/// the system is solved by least squares. Returns `None` if the stacks
+/// are not numeric where they should be or the system is degenerate.
+pub fn linear_fit(probes: &[Vec<Vec<UVal>>], expected: &[Vec<UVal>]) -> Option<Vec<f64>> {
+    let count = probes.len().checked_sub(1)?;
+    let mut rows: Vec<Vec<f64>> = Vec::new();
+    let mut rhs: Vec<f64> = Vec::new();
+
+    for (example, want) in expected.iter().enumerate() {
+        for (slot, target) in want.iter().enumerate() {
+            let UVal::Number(target) = target else {
+                continue;
+            };
+            let at = |probe: &Vec<Vec<UVal>>| match probe[example].get(slot) {
+                Some(UVal::Number(n)) if probe[example].len() == want.len() && n.is_finite() => {
+                    Some(*n)
+                }
+                _ => None,
+            };
+            let base = at(&probes[0])?;
+            let row = probes[1..]
+                .iter()
+                .map(|probe| Some(at(probe)? - base))
+                .collect::<Option<Vec<f64>>>()?;
+            rows.push(row);
+            rhs.push(target - base);
+        }
+    }
+    if rows.len() < count {
+        return None;
+    }
+    least_squares(&rows, &rhs)
+}
+
+/// Solves `rows * x = rhs` in the least-squares sense through the normal
+/// equations.
+fn least_squares(rows: &[Vec<f64>], rhs: &[f64]) -> Option<Vec<f64>> {
+    let n = rows.first()?.len();
+    let mut system = vec![vec![0.0; n + 1]; n];
+    for (row, b) in rows.iter().zip(rhs) {
+        for i in 0..n {
+            for j in 0..n {
+                system[i][j] += row[i] * row[j];
+            }
+            system[i][n] += row[i] * b;
+        }
+    }
+
+    // Gaussian elimination with partial pivoting.
+    for col in 0..n {
+        let pivot =
+            (col..n).max_by(|&a, &b| system[a][col].abs().total_cmp(&system[b][col].abs()))?;
+        if system[pivot][col].abs() < 1e-9 {
+            return None;
+        }
+        system.swap(col, pivot);
+        let pivot_row = system[col].clone();
+        for (row, equation) in system.iter_mut().enumerate() {
+            if row != col {
+                let factor = equation[col] / pivot_row[col];
+                for (value, pivot_value) in equation.iter_mut().zip(&pivot_row).skip(col) {
+                    *value -= factor * pivot_value;
+                }
+            }
+        }
+    }
+    let solution: Vec<f64> = (0..n).map(|i| system[i][n] / system[i][i]).collect();
+    solution.iter().all(|x| x.is_finite()).then_some(solution)
+}
@@ -0,0 +1,249 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+use crate::logic::{
+    StackEffect, infer_stack_effect, instruction_starts, rewrite_skill_calls, skill_calls,
+};
+use crate::persistence::{FNV_OFFSET, fnv1a_extend as fnv};
+use crate::types::{SkillLibrary, UVal};
+use crate::vm::{Op, SoulGainVM};
+
+/// Values the numeric probe stacks are drawn from: signs, zero, fractions and
+/// values large enough to tell `Mul` from `Add`.
+pub const PROBE_VALUES: [f64; 11] = [0.0, 1.0, -1.0, 2.0, 3.0, 7.0, -5.0, 0.5, 10.0, -12.5, 100.0];
+/// Values the string probe stacks are drawn from. `Add` concatenates strings,
+/// so these tell apart programs that agree on every number, such as
+/// `Swap Add` and `Add`.
+pub const PROBE_STRINGS: [&str; 4] = ["", "a", "bc", "42"];
+/// Probe stacks run per fingerprint: numeric ones, then all-string,
+/// all-bool and mixed-kind ones.
+pub const PROBE_COUNT: usize = NUMERIC_PROBES + 8;
+const NUMERIC_PROBES: usize = 16;
+const PROBE_CYCLES: usize = 2_000;
+
+/// Ops whose result depends on (or changes) state outside the stack. Skills
+/// that can reach one are never fingerprinted.
+const IMPURE_OPS: [Op; 5] = [Op::Store, Op::Load, Op::Reward, Op::Evolve, Op::Intuition];
+
+/// What a program does to the probe stacks. Programs with equal fingerprints
+/// are indistinguishable on every probe, which is strong evidence (not
+/// proof) that they compute the same function.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Fingerprint {
+    pub effect: StackEffect,
+    pub hash: u64,
+}
+
+/// Skills with one fingerprint. `canonical` does not call any of the
+/// `duplicates`, so references can be redirected to it without a cycle.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DuplicateGroup {
+    pub fingerprint: Fingerprint,
+    pub canonical: i64,
+    pub duplicates: Vec<i64>,
+}
+
+/// Deterministic probe stacks of `arity` values each.
+pub fn probe_inputs(arity: usize) -> Vec<Vec<UVal>> {
+    (0..PROBE_COUNT)
+        .map(|probe| (0..arity).map(|slot| probe_value(probe, slot)).collect())
+        .collect()
+}
+
+fn probe_value(probe: usize, slot: usize) -> UVal {
+    let pick = probe * 7 + slot * 3 + probe / 4;
+    let number = || UVal::Number(PROBE_VALUES[pick % PROBE_VALUES.len()]);
+    let string = || {
+        UVal::String(Arc::new(
+            PROBE_STRINGS[pick % PROBE_STRINGS.len()].to_string(),
+        ))
+    };
+    let boolean = || UVal::Bool((probe + slot).is_multiple_of(2));
+    match probe.checked_sub(NUMERIC_PROBES) {
+        None => number(),
+        Some(0..4) => string(),
+        Some(4..6) => boolean(),
+        Some(_) => match (probe + slot) % 3 {
+            0 => number(),
+            1 => string(),
+            _ => boolean(),
+        },
+    }
+}
+
+/// Runs programs against the probe stacks on a private VM, so probing never
+/// touches the caller's stack, plasticity or intuition state.
+pub struct Prober {
+    vm: SoulGainVM,
+}
+
+impl Prober {
+    pub fn new(skills: &SkillLibrary) -> Self {
+        let mut vm = SoulGainVM::new(Vec::new());
+        vm.skills = skills.clone();
+        Self { vm }
+    }
+
+    /// Brings the prober's copy of the library in line with `skills`. Only
+    /// skills added, changed or removed since the last sync are touched.
+    pub fn sync(&mut self, skills: &SkillLibrary) {
+        self.vm.skills.sync_from(skills);
+    }
+
+    /// Fingerprint of a skill body (or any program without a trailing
+    /// `Halt`). `None` if its stack effect is unknown or it can reach an op
+    /// with side effects.
+    pub fn fingerprint(&mut self, program: &[f64]) -> Option<Fingerprint> {
+        let effect = infer_stack_effect(program, &self.vm.skills, &mut HashMap::new()).ok()?;
+        if !is_pure(program, &self.vm.skills) {
+            return None;
+        }
+
+        let mut hash = FNV_OFFSET;
+        hash = fnv(hash, &(effect.inputs as u64).to_le_bytes());
+        hash = fnv(hash, &(effect.outputs as u64).to_le_bytes());
+        for input in probe_inputs(effect.inputs) {
+            self.vm.reset_execution();
+            self.vm.program = program.to_vec();
+            self.vm.program.push(Op::Halt.as_f64());
+            self.vm.stack = input;
+            let errors_before = self.vm.error_count();
+            self.vm.run(PROBE_CYCLES);
+
+            hash = fnv(hash, &[(self.vm.error_count() != errors_before) as u8]);
+            hash = fnv(hash, &(self.vm.stack.len() as u64).to_le_bytes());
+            for value in &self.vm.stack {
+                hash = hash_value(hash, value);
+            }
+        }
+        Some(Fingerprint { effect, hash })
+    }
+
+    pub fn fingerprint_skill(&mut self, id: i64) -> Option<Fingerprint> {
+        self.fingerprint(&[id as f64])
+    }
+
+    /// True if `program` leaves exactly the expected stack on every one of
+    /// `examples`.
+    pub fn reproduces(&mut self, program: &[f64], examples: &[(Vec<UVal>, Vec<UVal>)]) -> bool {
+        examples.iter().all(|(input, expected)| {
+            self.vm.reset_execution();
+            self.vm.program = program.to_vec();
+            self.vm.program.push(Op::Halt.as_f64());
+            self.vm.stack = input.clone();
+            self.vm.run(PROBE_CYCLES);
+            self.vm.stack == *expected
+        })
+    }
+}
+
+/// True if neither `program` nor any skill it reaches uses an op in
+/// `IMPURE_OPS`.
+pub(crate) fn is_pure(program: &[f64], skills: &SkillLibrary) -> bool {
+    let impure = |body: &[f64]| {
+        instruction_starts(body).into_iter().any(|idx| {
+            Op::from_i64(body[idx].round() as i64).is_some_and(|op| IMPURE_OPS.contains(&op))
+        })
+    };
+    !impure(program)
+        && skills
+            .reachable_from(&skill_calls(program))
+            .iter()
+            .all(|id| !skills.get_skill(*id).is_some_and(|body| impure(body)))
+}
+
+/// Fingerprints of every skill that can be fingerprinted.
+pub fn fingerprint_library(skills: &SkillLibrary) -> BTreeMap<i64, Fingerprint> {
+    let mut prober = Prober::new(skills);
+    skills
+        .ids()
+        .into_iter()
+        .filter_map(|id| prober.fingerprint_skill(id).map(|fp| (id, fp)))
+        .collect()
+}
+
+/// Groups skills whose fingerprints match. The canonical member is one that
+/// calls no other member, preferring the shortest body and then the lowest id.
+pub fn find_duplicates(skills: &SkillLibrary) -> Vec<DuplicateGroup> {
+    let mut by_fingerprint: BTreeMap<(u64, usize, usize), Vec<i64>> = BTreeMap::new();
+    let mut fingerprints = HashMap::new();
+    for (id, fp) in fingerprint_library(skills) {
+        by_fingerprint
+            .entry((fp.hash, fp.effect.inputs, fp.effect.outputs))
+            .or_default()
+            .push(id);
+        fingerprints.insert(id, fp);
+    }
+
+    let mut groups = Vec::new();
+    for members in by_fingerprint.into_values().filter(|m| m.len() > 1) {
+        let independent = |id: &i64| {
+            let reach = skills.reachable_from(&skills.dependencies(*id));
+            members
+                .iter()
+                .all(|other| other == id || !reach.contains(other))
+        };
+        let Some(canonical) = members
+            .iter()
+            .copied()
+            .filter(independent)
+            .min_by_key(|id| (skills.get_skill(*id).map_or(0, Vec::len), *id))
+        else {
+            continue;
+        };
+        groups.push(DuplicateGroup {
+            fingerprint: fingerprints[&canonical],
+            canonical,
+            duplicates: members.into_iter().filter(|id| *id != canonical).collect(),
+        });
+    }
+    groups
+}
+
+/// Redirects every call to a duplicate to its canonical skill, then removes
+/// the duplicates. Matching probes are only evidence, so a duplicate is
+/// merged only if it has recorded examples and the canonical skill
+/// reproduces every one of them; the others are left alone. Returns the
+/// applied `duplicate -> canonical` map so callers can rewrite programs that
+/// live outside the library.
+pub fn merge_duplicates(skills: &mut SkillLibrary, groups: &[DuplicateGroup]) -> HashMap<i64, i64> {
+    let mut prober = Prober::new(skills);
+    let mut remap = HashMap::new();
+    for group in groups {
+        for dup in &group.duplicates {
+            let examples = skills.info(*dup).map_or(&[][..], |info| &info.examples);
+            if !examples.is_empty() && prober.reproduces(&[group.canonical as f64], examples) {
+                remap.insert(*dup, group.canonical);
+            }
+        }
+    }
+    if remap.is_empty() {
+        return remap;
+    }
+    skills.retain(|id| !remap.contains_key(&id));
+    skills.rewrite_bodies(|_, body| rewrite_skill_calls(body, &remap));
+    remap
+}
+
+/// Hash of several stacks, as probe fingerprints hash them.
+pub(crate) fn hash_stacks(stacks: &[Vec<UVal>]) -> u64 {
+    let mut hash = FNV_OFFSET;
+    for stack in stacks {
+        hash = fnv(hash, &(stack.len() as u64).to_le_bytes());
+        for value in stack {
+            hash = hash_value(hash, value);
+        }
+    }
+    hash
+}
+
+fn hash_value(hash: u64, value: &UVal) -> u64 {
+    match value {
+        UVal::Nil => fnv(hash, &[0]),
+        UVal::Bool(b) => fnv(hash, &[1, *b as u8]),
+        // Normalise -0.0 so it matches 0.0, as `==` does.
+        UVal::Number(n) => fnv(fnv(hash, &[2]), &(n + 0.0).to_bits().to_le_bytes()),
+        UVal::String(s) => fnv(fnv(hash, &[3]), s.as_bytes()),
+        UVal::Object(_) => fnv(hash, &[4]),
+    }
+}
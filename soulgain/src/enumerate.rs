@@ -0,0 +1,210 @@
+use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+
+use crate::dedup::{hash_stacks, is_pure};
+use crate::logic::all_ops;
+use crate::types::{SkillLibrary, UVal};
+use crate::vm::{Op, SoulGainVM};
+
+/// Constants the enumerator tries as `Literal` operands.
+pub const ENUM_CONSTANTS: [f64; 5] = [0.0, 1.0, 2.0, 3.0, 10.0];
+const STEP_CYCLES: usize = 2_000;
+
+/// Ops that never appear in enumerated programs: control flow (programs are
+/// straight-line), `Literal` (added per constant) and everything that reads
+/// or writes state outside the stack, which would break observational
+/// equivalence.
+const EXCLUDED_OPS: [Op; 14] = [
+    Op::Literal,
+    Op::Halt,
+    Op::Jmp,
+    Op::JmpIf,
+    Op::JmpRel,
+    Op::JmpIfRel,
+    Op::Call,
+    Op::CallRel,
+    Op::Ret,
+    Op::Store,
+    Op::Load,
+    Op::Reward,
+    Op::Evolve,
+    Op::Intuition,
+];
+
+/// What the last enumeration did.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EnumerationStats {
+    /// Candidate programs run on the examples.
+    pub evaluated: usize,
+    /// Candidates kept because their outputs differed from every program
+    /// seen before.
+    pub distinct: usize,
+    /// Length (in instructions) of the last completed level.
+    pub depth: usize,
+}
+
+/// A program together with the stack it leaves on each example.
+struct Candidate {
+    logic: Vec<f64>,
+    stacks: Behaviour,
+}
+
+/// The stacks a program leaves on every example. Hashed like
+/// `dedup::hash_stacks`, but compared stack by stack, so behaviours whose
+/// hashes collide are still told apart.
+#[derive(Clone)]
+struct Behaviour(Rc<Vec<Vec<UVal>>>);
+
+impl PartialEq for Behaviour {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl Eq for Behaviour {}
+
+impl Hash for Behaviour {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_u64(hash_stacks(&self.0));
+    }
+}
+
+/// Bottom-up synthesis: programs are built by appending one instruction to
+/// the programs of the previous length, and a program is kept only if its
+/// stacks on the examples differ from every program kept so far. Two
+/// programs with the same stacks behave the same under any extension, so
+/// this enumerates every distinct behaviour in order of length and finds a
+/// shortest straight-line solution first.
+pub struct Enumerator {
+    vm: SoulGainVM,
+    alphabet: Vec<Vec<f64>>,
+    pub stats: EnumerationStats,
+}
+
+impl Enumerator {
+    /// Enumerates over the pure primitives, a `Literal` per `ENUM_CONSTANTS`
+    /// entry and every pure skill in `skills`.
+    pub fn new(skills: &SkillLibrary) -> Self {
+        let mut alphabet: Vec<Vec<f64>> = ENUM_CONSTANTS
+            .iter()
+            .map(|c| vec![Op::Literal.as_f64(), *c])
+            .collect();
+        alphabet.extend(
+            all_ops()
+                .iter()
+                .filter(|op| !EXCLUDED_OPS.contains(op))
+                .map(|op| vec![op.as_f64()]),
+        );
+        alphabet.extend(
+            skills
+                .ids()
+                .into_iter()
+                .filter(|id| is_pure(&[*id as f64], skills))
+                .map(|id| vec![id as f64]),
+        );
+
+        let mut vm = SoulGainVM::new(Vec::new());
+        vm.skills = skills.clone();
+        Self {
+            vm,
+            alphabet,
+            stats: EnumerationStats::default(),
+        }
+    }
+
+    /// Shortest programs first, returns the first one (without `Halt`) that
+    /// reproduces every example exactly and passes `accept`. Gives up after
+    /// `max_len` instructions, `budget` evaluated candidates, or as soon as
+    /// `stop` (checked before every candidate) returns true.
+    ///
+    /// Programs are also run on `unlabelled` inputs, such as held-out ones.
+    /// Their outputs are never compared with anything, but they keep apart
+    /// programs that agree on the examples: otherwise every program that
+    /// solves the examples would be a duplicate of the first one, and only
+    /// that one could ever be offered to `accept`.
+    pub fn search<F, S>(
+        &mut self,
+        examples: &[(Vec<UVal>, Vec<UVal>)],
+        unlabelled: &[Vec<UVal>],
+        max_len: usize,
+        budget: usize,
+        mut accept: F,
+        mut stop: S,
+    ) -> Option<Vec<f64>>
+    where
+        F: FnMut(&[f64]) -> bool,
+        S: FnMut(&EnumerationStats) -> bool,
+    {
+        self.stats = EnumerationStats::default();
+        if examples.is_empty() {
+            return None;
+        }
+        let inputs = Behaviour(Rc::new(
+            examples
+                .iter()
+                .map(|(input, _)| input.clone())
+                .chain(unlabelled.iter().cloned())
+                .collect(),
+        ));
+        let mut seen = HashSet::from([inputs.clone()]);
+        let mut level = vec![Candidate {
+            logic: Vec::new(),
+            stacks: inputs,
+        }];
+
+        for depth in 1..=max_len {
+            let mut next = Vec::new();
+            for parent in &level {
+                for instr in &self.alphabet {
+                    if self.stats.evaluated >= budget || stop(&self.stats) {
+                        return None;
+                    }
+                    self.stats.evaluated += 1;
+                    let Some(stacks) = step(&mut self.vm, &parent.stacks.0, instr) else {
+                        continue;
+                    };
+                    let stacks = Behaviour(Rc::new(stacks));
+                    if !seen.insert(stacks.clone()) {
+                        continue;
+                    }
+                    self.stats.distinct += 1;
+
+                    let mut logic = parent.logic.clone();
+                    logic.extend_from_slice(instr);
+                    let solved = stacks
+                        .0
+                        .iter()
+                        .zip(examples)
+                        .all(|(stack, (_, expected))| stack == expected);
+                    if solved && accept(&logic) {
+                        return Some(logic);
+                    }
+                    next.push(Candidate { logic, stacks });
+                }
+            }
+            self.stats.depth = depth;
+            if next.is_empty() {
+                break;
+            }
+            level = next;
+        }
+        None
+    }
+}
+
+/// Runs `instr` on each stack. `None` if it errors on any of them.
+fn step(vm: &mut SoulGainVM, stacks: &[Vec<UVal>], instr: &[f64]) -> Option<Vec<Vec<UVal>>> {
+    stacks
+        .iter()
+        .map(|stack| {
+            vm.reset_execution();
+            vm.program = instr.to_vec();
+            vm.program.push(Op::Halt.as_f64());
+            vm.stack = stack.clone();
+            let errors_before = vm.error_count();
+            vm.run(STEP_CYCLES);
+            (vm.error_count() == errors_before).then(|| std::mem::take(&mut vm.stack))
+        })
+        .collect()
+}
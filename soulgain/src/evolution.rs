@@ -1,14 +1,122 @@
+use crate::budget::{
+    CancellationToken, Monitor, Progress, ProgressCallback, SearchEvent, StopReason,
+};
+use crate::constants::{self, HOLE, MAX_ENUM_HOLES, MAX_FIT_HOLES};
+use crate::dedup::{Fingerprint, Prober, is_pure};
+use crate::enumerate::Enumerator;
+use crate::fitness::{Fitness, TypeAware};
+use crate::genetic::{GeneticConfig, crossover, tournament};
 use crate::hypothesis::Hypothesis;
+use crate::logic::{has_operand, instruction_starts, rewrite_skill_calls, skill_calls};
+use crate::optimize::extract_window;
 use crate::plasticity::Event;
-use crate::types::UVal;
+use crate::strategy::{SearchContext, StrategyRegistry, StrategyStats};
+use crate::typecheck::{KindSet, infer_types};
+use crate::types::{SkillInfo, SkillLibrary, SkillNamespace, UVal};
 use crate::{Op, SKILL_OPCODE_BASE, SoulGainVM};
-use rand::Rng;
-use std::collections::HashSet;
-use std::fs::OpenOptions;
-use std::io::Write;
+use rand::seq::SliceRandom;
+use rand::{Rng, RngCore};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::{Arc, mpsc};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Oracle inputs `Trainer::synthesize_with_oracle` starts from.
+pub const CEGIS_SEED_INPUTS: usize = 4;
+
+/// Most counterexamples `Trainer::synthesize_with_oracle` adds before it
+/// gives up.
+pub const CEGIS_MAX_ROUNDS: usize = 32;
 
 pub trait Oracle {
     fn evaluate(&self, input: Vec<UVal>) -> Vec<UVal>;
+
+    /// A fresh input to validate candidates on. Oracles that cannot invent
+    /// inputs return `None`, and only the held-out examples are used.
+    fn generate_input(&self, _rng: &mut dyn RngCore) -> Option<Vec<UVal>> {
+        None
+    }
+}
+
+/// What a parallel worker hands back to the coordinator.
+struct WorkerResult {
+    report: SynthesisReport,
+    skills: SkillLibrary,
+    stats: Vec<(String, StrategyStats)>,
+}
+
+/// How `Trainer::synthesize` looks for candidates.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SearchMode {
+    /// Randomized hill-climbing over hypotheses, STDP-guided builds,
+    /// mutation and skill speculation.
+    #[default]
+    Stochastic,
+    /// Deterministic bottom-up enumeration with observational equivalence
+    /// (see `enumerate::Enumerator`). Finds a shortest straight-line
+    /// solution, but only within `max_program_len` and the attempt budget.
+    Enumerative,
+    /// Population-based genetic programming (see `genetic::GeneticConfig`):
+    /// tournament selection with fitness sharing, subsequence crossover,
+    /// elitism and random immigrants, with duplicate programs kept out of
+    /// each generation. Only pure programs are bred.
+    Genetic,
+}
+
+/// An example a candidate got wrong during validation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidationFailure {
+    pub input: Vec<UVal>,
+    pub expected: Vec<UVal>,
+    pub got: Vec<UVal>,
+}
+
+/// Outcome of `Trainer::synthesize_report`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SynthesisReport {
+    /// The promoted program, or the closest candidate if none generalized.
+    pub program: Option<Vec<f64>>,
+    /// Skill the program was promoted to.
+    pub skill: Option<i64>,
+    /// Strategy (or search mode) that produced the promoted program.
+    pub strategy: Option<String>,
+    /// Fraction of training examples `program` solves exactly.
+    pub train_accuracy: f64,
+    /// Fraction of validation examples (held-out and oracle-generated)
+    /// `program` solves exactly, or `None` if there were none.
+    pub validation_accuracy: Option<f64>,
+    /// Validation examples `program` got wrong.
+    pub failures: Vec<ValidationFailure>,
+    /// Candidates considered before the run ended.
+    pub evaluations: usize,
+    /// Set if the run hit its deadline or was cancelled.
+    pub stopped: Option<StopReason>,
+}
+
+impl SynthesisReport {
+    /// True if a program solves every training and validation example.
+    pub fn generalizes(&self) -> bool {
+        self.program.is_some()
+            && self.train_accuracy >= 1.0
+            && self.validation_accuracy.is_none_or(|acc| acc >= 1.0)
+    }
+
+    /// True if `program` solves the training set but not validation.
+    fn counterexample(&self) -> bool {
+        self.program.is_some() && self.train_accuracy >= 1.0 && !self.failures.is_empty()
+    }
+
+    fn accepted(self) -> Option<Vec<f64>> {
+        if self.generalizes() {
+            self.program
+        } else {
+            None
+        }
+    }
 }
 
 pub struct Trainer {
@@ -17,6 +125,31 @@ pub struct Trainer {
     max_program_len: usize,
     explore_rate: f64,
     program_buf: Vec<f64>,
+    validation_fraction: f64,
+    oracle_samples: usize,
+    search_mode: SearchMode,
+    genetic: GeneticConfig,
+    strategies: StrategyRegistry,
+    time_limit: Option<Duration>,
+    cancellation: CancellationToken,
+    progress: Option<ProgressCallback>,
+    workers: usize,
+    fitness: Arc<dyn Fitness>,
+    oracle_tests: usize,
+    /// Set while `synthesize_with_oracle` runs: a search returns the first
+    /// candidate that solves its examples but fails validation.
+    stop_at_counterexample: bool,
+    /// Set while `synthesize_with_oracle` runs: when the run started and the
+    /// evaluations of its earlier rounds, so progress counts across rounds.
+    resume: Option<(Instant, usize)>,
+    /// Candidate log written by `log_logic`.
+    trace: Option<File>,
+    /// Fingerprints of library skills, keyed by id and stored with the body
+    /// they were computed for.
+    fingerprints: HashMap<i64, (Vec<f64>, Option<Fingerprint>)>,
+    /// Runs the fingerprint probes. Built on first use and synced with the
+    /// library before each use.
+    prober: Option<Prober>,
 }
 
 impl Trainer {
@@ -27,9 +160,128 @@ impl Trainer {
             max_program_len,
             explore_rate: 0.3,
             program_buf: Vec::new(),
+            validation_fraction: 0.25,
+            oracle_samples: 32,
+            search_mode: SearchMode::default(),
+            genetic: GeneticConfig::default(),
+            strategies: StrategyRegistry::default(),
+            time_limit: None,
+            cancellation: CancellationToken::new(),
+            progress: None,
+            workers: 1,
+            fitness: Arc::new(TypeAware),
+            oracle_tests: 256,
+            stop_at_counterexample: false,
+            resume: None,
+            trace: None,
+            fingerprints: HashMap::new(),
+            prober: None,
         }
     }
 
+    /// Fraction of the examples `synthesize` and `synthesize_report` hold
+    /// back for validation (default 0.25; 0 trains on every example). Sets
+    /// of fewer than four examples are never split.
+    pub fn with_validation_fraction(mut self, fraction: f64) -> Self {
+        self.validation_fraction = fraction.clamp(0.0, 0.9);
+        self
+    }
+
+    pub fn with_search_mode(mut self, mode: SearchMode) -> Self {
+        self.search_mode = mode;
+        self
+    }
+
+    /// Wall-clock limit for each synthesis run. A run that hits it returns
+    /// the best it has, with `SynthesisReport::stopped` set.
+    pub fn with_time_limit(mut self, limit: Duration) -> Self {
+        self.time_limit = Some(limit);
+        self
+    }
+
+    /// Stops runs once `token` is cancelled, e.g. from another thread.
+    pub fn with_cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancellation = token;
+        self
+    }
+
+    /// Called every `budget::PROGRESS_INTERVAL` candidates and whenever the
+    /// best fitness improves.
+    pub fn with_progress<F>(mut self, callback: F) -> Self
+    where
+        F: FnMut(&Progress) + Send + 'static,
+    {
+        self.progress = Some(Box::new(callback));
+        self
+    }
+
+    /// Runs each search on `workers` threads (see `search_parallel`), all
+    /// logging to the trace file if there is one. A stochastic search stays
+    /// on the calling thread while a registered strategy cannot be forked
+    /// (see `SearchStrategy::fork`), and an enumerative one always does.
+    pub fn with_workers(mut self, workers: usize) -> Self {
+        self.workers = workers.max(1);
+        self
+    }
+
+    /// Fresh oracle inputs `synthesize_with_oracle` checks each candidate
+    /// on (default 256).
+    pub fn with_oracle_tests(mut self, tests: usize) -> Self {
+        self.oracle_tests = tests;
+        self
+    }
+
+    /// How candidates are scored during search (default
+    /// `fitness::TypeAware`).
+    pub fn with_fitness(mut self, fitness: impl Fitness + 'static) -> Self {
+        self.fitness = Arc::new(fitness);
+        self
+    }
+
+    /// Switches the fitness between tasks on the same trainer.
+    pub fn set_fitness(&mut self, fitness: impl Fitness + 'static) {
+        self.fitness = Arc::new(fitness);
+    }
+
+    pub fn fitness(&self) -> &dyn Fitness {
+        self.fitness.as_ref()
+    }
+
+    /// Appends every scored candidate of the stochastic search to `path`
+    /// (decoded, with its fitness). Slow; meant for debugging.
+    pub fn with_trace_log<P: AsRef<Path>>(mut self, path: P) -> io::Result<Self> {
+        self.trace = Some(OpenOptions::new().create(true).append(true).open(path)?);
+        Ok(self)
+    }
+
+    /// Token that cancels this trainer's runs. Clones can be sent to
+    /// other threads.
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.cancellation.clone()
+    }
+
+    /// Strategies `SearchMode::Stochastic` draws from, with their weights
+    /// and statistics.
+    pub fn strategies(&self) -> &StrategyRegistry {
+        &self.strategies
+    }
+
+    pub fn strategies_mut(&mut self) -> &mut StrategyRegistry {
+        &mut self.strategies
+    }
+
+    pub fn with_genetic_config(mut self, config: GeneticConfig) -> Self {
+        self.genetic = config;
+        self
+    }
+
+    /// Inputs drawn from the oracle to validate a winning candidate
+    /// (default 32).
+    pub fn with_oracle_samples(mut self, samples: usize) -> Self {
+        self.oracle_samples = samples;
+        self
+    }
+
     fn normalize_depth(depth: usize) -> usize {
         // Treat any stack depth >= 5 as simply "5" (Enough items for complex skills)
         // This prevents overfitting to specific stack sizes.
@@ -80,106 +332,479 @@ impl Trainer {
         0
     }
 
+    /// Searches for a program that solves `examples`, holding some of them
+    /// back for validation (see `with_validation_fraction`). Returns the
+    /// program only if it solves those too.
     pub fn synthesize(
         &mut self,
         examples: &[(Vec<UVal>, Vec<UVal>)],
         attempts_limit: usize,
     ) -> Option<Vec<f64>> {
+        self.synthesize_report(examples, None, attempts_limit)
+            .accepted()
+    }
+
+    /// Splits `examples` into training and validation sets (see
+    /// `with_validation_fraction`) and, if `oracle` can generate inputs,
+    /// adds oracle-labelled inputs to the validation set. A candidate that solves the training set is promoted to a skill
+    /// only if it also solves every validation example.
+    pub fn synthesize_report(
+        &mut self,
+        examples: &[(Vec<UVal>, Vec<UVal>)],
+        oracle: Option<&dyn Oracle>,
+        attempts_limit: usize,
+    ) -> SynthesisReport {
+        let mut order: Vec<usize> = (0..examples.len()).collect();
+        let mut held = 0;
+        if examples.len() >= 4 {
+            order.shuffle(&mut self.rng);
+            held = ((examples.len() as f64 * self.validation_fraction).round() as usize)
+                .min(examples.len() - 1);
+        }
+        let (validation, train) = order.split_at(held);
+        let train: Vec<_> = train.iter().map(|&i| examples[i].clone()).collect();
+        let mut holdout: Vec<_> = validation.iter().map(|&i| examples[i].clone()).collect();
+
+        if let Some(oracle) = oracle {
+            let samples = self.oracle_examples(oracle, self.oracle_samples);
+            holdout.extend(samples);
+        }
+        self.search(&train, &holdout, attempts_limit)
+    }
+
+    /// Like `synthesize`, but with an explicit validation set: a candidate
+    /// that solves `examples` is only accepted if it also reproduces every
+    /// `holdout` example exactly. The search never scores candidates on
+    /// `holdout`, so passing it is evidence of generalization rather than
+    /// memorization.
+    pub fn synthesize_with_holdout(
+        &mut self,
+        examples: &[(Vec<UVal>, Vec<UVal>)],
+        holdout: &[(Vec<UVal>, Vec<UVal>)],
+        attempts_limit: usize,
+    ) -> Option<Vec<f64>> {
+        self.search(examples, holdout, attempts_limit).accepted()
+    }
+
+    /// Counterexample-guided synthesis. Starts from `CEGIS_SEED_INPUTS`
+    /// oracle-labelled inputs; whenever a candidate solves them but
+    /// disagrees with the oracle on one of `oracle_tests` fresh inputs, that
+    /// input joins the examples and the search starts over. Ends once a
+    /// candidate agrees on every test, the search comes up empty, or after
+    /// `CEGIS_MAX_ROUNDS` counterexamples. The oracle must be able to
+    /// generate inputs. The time limit covers the whole run; each round gets
+    /// what is left of it.
+    pub fn synthesize_with_oracle(
+        &mut self,
+        oracle: &dyn Oracle,
+        attempts_limit: usize,
+    ) -> SynthesisReport {
+        let mut examples = self.oracle_examples(oracle, CEGIS_SEED_INPUTS);
         if examples.is_empty() {
-            return None;
+            return SynthesisReport::default();
+        }
+        let previous = std::mem::replace(&mut self.stop_at_counterexample, true);
+        let time_limit = self.time_limit;
+        let started = Instant::now();
+        let mut monitor = Monitor::new(time_limit, self.cancellation.clone(), self.progress.take());
+        let mut report = SynthesisReport::default();
+
+        for round in 0..=CEGIS_MAX_ROUNDS {
+            let tests = self.oracle_examples(oracle, self.oracle_tests);
+            self.time_limit = monitor.remaining();
+            self.progress = monitor.take_callback();
+            self.resume = Some((started, monitor.evaluations()));
+            report = self.search(&examples, &tests, attempts_limit);
+            self.resume = None;
+            monitor.give_callback(self.progress.take());
+            monitor.absorb(report.evaluations);
+            if report.stopped.is_some() || !report.counterexample() || round == CEGIS_MAX_ROUNDS {
+                break;
+            }
+            let failure = &report.failures[0];
+            examples.push((failure.input.clone(), failure.expected.clone()));
+            monitor.report(SearchEvent::Counterexample {
+                round: round + 1,
+                input: failure.input.clone(),
+                expected: failure.expected.clone(),
+            });
         }
 
+        self.stop_at_counterexample = previous;
+        self.time_limit = time_limit;
+        report.evaluations = monitor.evaluations();
+        self.progress = monitor.into_callback();
+        report
+    }
+
+    /// Up to `count` oracle inputs, labelled by the oracle.
+    fn oracle_examples(
+        &mut self,
+        oracle: &dyn Oracle,
+        count: usize,
+    ) -> Vec<(Vec<UVal>, Vec<UVal>)> {
+        let mut examples = Vec::with_capacity(count);
+        for _ in 0..count {
+            let Some(input) = oracle.generate_input(&mut self.rng) else {
+                break;
+            };
+            let expected = oracle.evaluate(input.clone());
+            examples.push((input, expected));
+        }
+        examples
+    }
+
+    fn search(
+        &mut self,
+        examples: &[(Vec<UVal>, Vec<UVal>)],
+        holdout: &[(Vec<UVal>, Vec<UVal>)],
+        attempts_limit: usize,
+    ) -> SynthesisReport {
+        if examples.is_empty() {
+            return SynthesisReport::default();
+        }
+        if self.workers > 1 {
+            // Only stochastic search draws from the strategies, and every
+            // worker needs its own copy of them. Enumeration is
+            // deterministic, so more workers would only repeat it.
+            let registries: Option<Vec<StrategyRegistry>> = match self.search_mode {
+                SearchMode::Stochastic => {
+                    (0..self.workers).map(|_| self.strategies.fork()).collect()
+                }
+                SearchMode::Enumerative => None,
+                SearchMode::Genetic => Some(
+                    (0..self.workers)
+                        .map(|_| StrategyRegistry::empty())
+                        .collect(),
+                ),
+            };
+            if let Some(registries) = registries {
+                return self.search_parallel(examples, holdout, attempts_limit, registries);
+            }
+        }
+        let all_examples: Vec<(Vec<UVal>, Vec<UVal>)> =
+            examples.iter().chain(holdout).cloned().collect();
+        let mut monitor = Monitor::new(
+            self.time_limit,
+            self.cancellation.clone(),
+            self.progress.take(),
+        );
+        if let Some((started, evaluations)) = self.resume {
+            monitor = monitor.resume(started, evaluations);
+        }
+        let report = match self.search_mode {
+            SearchMode::Stochastic => {
+                // Strategies get `&mut self`, so the registry is lent out
+                // for the duration of the search.
+                let mut strategies =
+                    std::mem::replace(&mut self.strategies, StrategyRegistry::empty());
+                let report = self.search_stochastic(
+                    &mut strategies,
+                    &mut monitor,
+                    examples,
+                    holdout,
+                    &all_examples,
+                    attempts_limit,
+                );
+                self.strategies = strategies;
+                report
+            }
+            SearchMode::Enumerative => self.search_enumerative(
+                &mut monitor,
+                examples,
+                holdout,
+                &all_examples,
+                attempts_limit,
+            ),
+            SearchMode::Genetic => self.search_genetic(
+                &mut monitor,
+                examples,
+                holdout,
+                &all_examples,
+                attempts_limit,
+            ),
+        };
+        let evaluations = monitor.evaluations();
+        let stopped = monitor.stopped();
+        self.progress = monitor.into_callback();
+        SynthesisReport {
+            evaluations,
+            stopped,
+            ..report
+        }
+    }
+
+    /// Runs `search` on `workers` threads at once. Each worker gets its own
+    /// VM and a copy of the skill library in its own namespace, so skills
+    /// they create can never clash, while every worker's plasticity feeds
+    /// the shared brain. The coordinator forwards progress, stops the other
+    /// workers once one finds a program that generalizes, and merges the
+    /// workers' non-speculative skills (and everything the winning program
+    /// calls) back into this trainer's library.
+    ///
+    /// Worker `i` searches with `registries[i]`, a fork of this trainer's
+    /// strategies, and a fresh intuition engine.
+    fn search_parallel(
+        &mut self,
+        examples: &[(Vec<UVal>, Vec<UVal>)],
+        holdout: &[(Vec<UVal>, Vec<UVal>)],
+        attempts_limit: usize,
+        registries: Vec<StrategyRegistry>,
+    ) -> SynthesisReport {
+        enum Message {
+            Progress(usize, Progress),
+            Done(usize, Box<WorkerResult>),
+        }
+
+        let run_token = CancellationToken::new();
+        let (tx, rx) = mpsc::channel::<Message>();
+        let mut results: Vec<Option<WorkerResult>> = (0..self.workers).map(|_| None).collect();
+        let (started, base) = self.resume.unwrap_or((Instant::now(), 0));
+        let mut callback = self.progress.take();
+        let mut cancelled = false;
+
+        thread::scope(|scope| {
+            for (index, strategies) in registries.into_iter().enumerate() {
+                let namespace = SkillNamespace::named(&format!(
+                    "{}/worker-{}",
+                    self.vm.skills.namespace, index
+                ));
+                let mut skills = self.vm.skills.clone();
+                skills.namespace = namespace;
+                let plasticity = self.vm.plasticity.clone();
+                let memory = self.vm.memory.clone();
+                let (max_len, mode, genetic, time_limit) = (
+                    self.max_program_len,
+                    self.search_mode,
+                    self.genetic,
+                    self.time_limit,
+                );
+                let fitness = Arc::clone(&self.fitness);
+                let trace = self.trace.as_ref().and_then(|file| file.try_clone().ok());
+                let stop_at_counterexample = self.stop_at_counterexample;
+                let token = run_token.clone();
+                let tx = tx.clone();
+                scope.spawn(move || {
+                    let mut vm = SoulGainVM::new(Vec::new());
+                    vm.skills = skills;
+                    vm.plasticity = plasticity;
+                    vm.memory = memory;
+                    let progress_tx = tx.clone();
+                    let mut worker = Trainer::new(vm, max_len)
+                        .with_search_mode(mode)
+                        .with_genetic_config(genetic)
+                        .with_cancellation(token)
+                        .with_progress(move |p: &Progress| {
+                            let _ = progress_tx.send(Message::Progress(index, p.clone()));
+                        });
+                    worker.time_limit = time_limit;
+                    worker.fitness = fitness;
+                    worker.trace = trace;
+                    worker.stop_at_counterexample = stop_at_counterexample;
+                    worker.strategies = strategies;
+
+                    let report = worker.search(examples, holdout, attempts_limit);
+                    let stats = worker
+                        .strategies
+                        .stats()
+                        .into_iter()
+                        .map(|(name, stats)| (name.to_string(), stats))
+                        .collect();
+                    let result = WorkerResult {
+                        report,
+                        skills: std::mem::take(&mut worker.vm.skills),
+                        stats,
+                    };
+                    let _ = tx.send(Message::Done(index, Box::new(result)));
+                });
+            }
+            drop(tx);
+
+            let mut latest: Vec<Option<Progress>> = vec![None; self.workers];
+            let mut pending = self.workers;
+            while pending > 0 {
+                if !cancelled && self.cancellation.is_cancelled() {
+                    cancelled = true;
+                    run_token.cancel();
+                }
+                let message = match rx.recv_timeout(Duration::from_millis(20)) {
+                    Ok(message) => message,
+                    Err(mpsc::RecvTimeoutError::Timeout) => continue,
+                    Err(mpsc::RecvTimeoutError::Disconnected) => break,
+                };
+                match message {
+                    Message::Progress(index, mut progress) => {
+                        // Events are forwarded once, never replayed with a
+                        // later worker's progress.
+                        let event = progress.event.take();
+                        latest[index] = Some(progress);
+                        let Some(callback) = callback.as_mut() else {
+                            continue;
+                        };
+                        // Report the best worker's view, with totals.
+                        let best = latest
+                            .iter()
+                            .flatten()
+                            .max_by(|a, b| a.best_fitness.total_cmp(&b.best_fitness))
+                            .expect("a progress message was just stored");
+                        callback(&Progress {
+                            evaluations: base
+                                + latest
+                                    .iter()
+                                    .flatten()
+                                    .map(|p| p.evaluations)
+                                    .sum::<usize>(),
+                            elapsed: started.elapsed(),
+                            event,
+                            ..best.clone()
+                        });
+                    }
+                    Message::Done(index, result) => {
+                        pending -= 1;
+                        // A counterexample restarts every worker's search.
+                        if result.report.generalizes()
+                            || (self.stop_at_counterexample && result.report.counterexample())
+                        {
+                            run_token.cancel();
+                        }
+                        results[index] = Some(*result);
+                    }
+                }
+            }
+        });
+        self.progress = callback;
+
+        let results: Vec<WorkerResult> = results.into_iter().flatten().collect();
+        let winner = results
+            .iter()
+            .enumerate()
+            .max_by(|(_, a), (_, b)| {
+                let key = |r: &SynthesisReport| {
+                    (
+                        r.generalizes(),
+                        r.skill.is_some(),
+                        r.train_accuracy,
+                        r.validation_accuracy.unwrap_or(0.0),
+                    )
+                };
+                let (ka, kb) = (key(&a.report), key(&b.report));
+                ka.0.cmp(&kb.0)
+                    .then(ka.1.cmp(&kb.1))
+                    .then(ka.2.total_cmp(&kb.2))
+                    .then(ka.3.total_cmp(&kb.3))
+            })
+            .map(|(index, _)| index);
+
+        let mut report = SynthesisReport::default();
+        for (index, result) in results.into_iter().enumerate() {
+            self.strategies.absorb_stats(&result.stats);
+            let mut keep: Vec<i64> = result
+                .skills
+                .root_skills()
+                .into_iter()
+                .filter(|id| !self.vm.skills.contains(*id))
+                .collect();
+            if Some(index) == winner
+                && let Some(program) = &result.report.program
+            {
+                keep.extend(skill_calls(program));
+            }
+            let keep = result.skills.reachable_from(&keep);
+            let mut incoming = result.skills;
+            incoming.retain(|id| keep.contains(&id));
+            // A worker whose skills no longer fit in the namespace, or would
+            // call themselves once merged, cannot contribute its result.
+            let Ok(merged) = self.vm.skills.merge(&incoming) else {
+                report.evaluations += result.report.evaluations;
+                continue;
+            };
+
+            let evaluations = report.evaluations + result.report.evaluations;
+            let stopped = report.stopped.or(result.report.stopped);
+            if Some(index) == winner {
+                let remap: HashMap<i64, i64> = merged.remapped.into_iter().collect();
+                report = result.report;
+                report.program = report
+                    .program
+                    .map(|program| rewrite_skill_calls(&program, &remap));
+                report.skill = report.skill.map(|id| remap.get(&id).copied().unwrap_or(id));
+            }
+            report.evaluations = evaluations;
+            report.stopped = stopped;
+        }
+        report.stopped = if cancelled {
+            Some(StopReason::Cancelled)
+        } else if report.generalizes() || (self.stop_at_counterexample && report.counterexample()) {
+            None
+        } else {
+            report.stopped
+        };
+        report
+    }
+
+    fn search_stochastic(
+        &mut self,
+        strategies: &mut StrategyRegistry,
+        monitor: &mut Monitor,
+        examples: &[(Vec<UVal>, Vec<UVal>)],
+        holdout: &[(Vec<UVal>, Vec<UVal>)],
+        all_examples: &[(Vec<UVal>, Vec<UVal>)],
+        attempts_limit: usize,
+    ) -> SynthesisReport {
+        // Closest candidate that solved the training set but not the
+        // validation set, reported if nothing generalizes.
+        let mut best_rejected: Option<SynthesisReport> = None;
+
         let input = examples[0].0.clone();
         let expected = examples[0].1.clone();
         let mut failed_attempts: HashSet<Vec<u64>> = HashSet::new();
         let mut best_program: Option<Vec<f64>> = None;
         let mut best_fitness = 0.0;
 
-        let input_preamble_len = 0;
+        let input_kinds = Self::input_kinds(all_examples);
         let shape_id = self.detect_problem_shape(&input, &expected);
 
-        for current_len in 1..=self.max_program_len {
+        'search: for current_len in 1..=self.max_program_len {
             failed_attempts.clear();
 
             for level_attempt in 1..=attempts_limit {
-                let r = self.rng.r#gen::<f64>();
-
-                // Strategy Selection Logic
-                let has_clue = best_program.is_some() && best_fitness > 0.0001;
-
-                let try_hypothesis = if !has_clue {
-                    // No clue? Guess wildly (Hypothesis) or use STDP (Random Build)
-                    r < 0.5
-                } else {
-                    // Have a clue? Only guess completely new things 10% of the time.
-                    r < 0.1
+                if !monitor.tick(current_len) {
+                    break 'search;
+                }
+                let cx = SearchContext {
+                    examples,
+                    target_len: current_len,
+                    best: best_program.as_deref().map(|p| (p, best_fitness)),
+                    shape: shape_id,
                 };
-
-                let try_speculation = !try_hypothesis && has_clue && r < 0.4;
-
-                let (current_program, logic_start, strategy) = if try_hypothesis {
-                    // --- HYPOTHESIS MODE (Fresh Guess) ---
-                    let skills: Vec<i64> = self.vm.skills.macros.keys().cloned().collect();
-                    let hypothesis = Hypothesis::generate(current_len, &skills);
-
-                    self.program_buf.clear();
-                    let start = self.program_buf.len();
-                    self.program_buf.extend_from_slice(&hypothesis.logic);
-
-                    if self.program_buf.last() != Some(&Op::Halt.as_f64()) {
-                        self.program_buf.push(Op::Halt.as_f64());
-                    }
-
-                    (self.program_buf.clone(), start, "HYPOTHESIS")
-                } else if try_speculation {
-                    // --- SPECULATION MODE (Optimization) ---
-                    let mut variant = best_program.clone().unwrap();
-                    let _id = self.speculate_new_skill(&mut variant, input_preamble_len);
-                    (variant, input_preamble_len, "SPEC")
-                } else if has_clue {
-                    // --- MUTATION / EXTEND MODE ---
-                    let mut variant = best_program.clone().unwrap();
-
-                    // If the best program is shorter than current_len, EXTEND it.
-                    let logic_len = variant.len().saturating_sub(input_preamble_len);
-                    if logic_len < current_len {
-                        // Remove HALT
-                        if variant.last() == Some(&Op::Halt.as_f64()) {
-                            variant.pop();
-                        }
-                        // Add a random op to grow it
-                        variant.push(self.choose_random_op_with_bias(input.len()) as f64);
-                        variant.push(Op::Halt.as_f64());
-                        (variant, input_preamble_len, "EXTEND")
-                    } else {
-                        // Standard mutation
-                        self.mutate_program(&mut variant, input_preamble_len);
-                        (variant, input_preamble_len, "MUTATE")
-                    }
-                } else {
-                    // --- RANDOM BUILD (STDP) ---
-                    let (_last_event, start) =
-                        self.build_program(&input, current_len, true, shape_id);
-                    (self.program_buf.clone(), start, "RANDOM")
+                let Some(index) = strategies.choose(&cx, &mut self.rng) else {
+                    continue;
+                };
+                let Some(current_program) = strategies.propose(index, self, &cx) else {
+                    continue;
                 };
+                let strategy = strategies.name(index).to_string();
 
-                let logic_bits: Vec<u64> = current_program[logic_start..]
-                    .iter()
-                    .map(|f| f.to_bits())
-                    .collect();
+                let logic_bits: Vec<u64> = current_program.iter().map(|f| f.to_bits()).collect();
                 if failed_attempts.contains(&logic_bits) {
                     continue;
                 }
                 failed_attempts.insert(logic_bits);
 
-                let logic = current_program[logic_start..].to_vec();
-                let (fitness, solved_all) = self.evaluate_logic_on_examples(&logic, examples);
+                // Malformed candidates (bad jumps, underflow on some path,
+                // loops with no exit) and ill-typed ones never reach the VM.
+                if !self.well_typed(&current_program, &input_kinds) {
+                    continue;
+                }
+                let current_program = self.solve_constants(monitor, &current_program, examples);
+                let (fitness, solved_all) =
+                    self.evaluate_logic_on_examples(&current_program, examples);
 
                 self.log_logic(
                     current_len,
                     level_attempt,
-                    strategy,
-                    &current_program[logic_start..],
+                    &strategy,
+                    &current_program,
                     fitness,
                 );
 
@@ -187,6 +812,8 @@ impl Trainer {
                 if fitness > best_fitness {
                     best_fitness = fitness;
                     best_program = Some(current_program.clone());
+                    strategies.record_improvement(index);
+                    monitor.improve(fitness, current_program.len(), &strategy);
                     // Give small rewards for ANY progress
                     self.vm
                         .plasticity
@@ -195,144 +822,610 @@ impl Trainer {
 
                 // --- SUCCESS & PRUNING BLOCK ---
                 if solved_all {
-                    let logic_slice = current_program[logic_start..].to_vec();
-                    let mut clean_logic = logic_slice;
-                    if clean_logic.last() == Some(&(Op::Halt.as_f64())) {
-                        clean_logic.pop();
+                    let report = self.score_program(&current_program, examples, holdout);
+                    if !report.generalizes() {
+                        Self::reject(
+                            monitor,
+                            &mut best_rejected,
+                            report,
+                            examples.len(),
+                            holdout.len(),
+                        );
+                        if self.stop_at_counterexample {
+                            break 'search;
+                        }
+                        continue;
                     }
+                    strategies.record_solution(index);
+                    return self.promote(
+                        monitor,
+                        &current_program,
+                        &strategy,
+                        current_len,
+                        all_examples,
+                        report,
+                    );
+                }
+            }
+        }
 
-                    // [NEW] Pruning Integration
-                    use crate::hypothesis::Pruner;
-                    let pruned_logic = Pruner::prune(&self.vm, &clean_logic, &input, &expected);
+        match (best_rejected, best_program) {
+            (Some(report), _) => report,
+            (None, Some(program)) => self.score_program(&program, examples, holdout),
+            (None, None) => SynthesisReport::default(),
+        }
+    }
 
-                    if !pruned_logic.is_empty() {
-                        let skill_id = self.register_or_find_skill(pruned_logic.clone());
+    /// Distinct kind signatures of the example inputs.
+    fn input_kinds(examples: &[(Vec<UVal>, Vec<UVal>)]) -> Vec<Vec<KindSet>> {
+        let mut input_kinds: Vec<Vec<KindSet>> = Vec::new();
+        for (example_input, _) in examples {
+            let kinds = KindSet::of_values(example_input);
+            if !input_kinds.contains(&kinds) {
+                input_kinds.push(kinds);
+            }
+        }
+        input_kinds
+    }
 
-                        // Optional: Log the optimization
-                        if clean_logic.len() > pruned_logic.len() {
-                            println!(
-                                "  [OPTIMIZED] Len {} -> Len {}",
-                                clean_logic.len(),
-                                pruned_logic.len()
-                            );
-                        }
+    fn well_typed(&self, logic: &[f64], input_kinds: &[Vec<KindSet>]) -> bool {
+        input_kinds
+            .iter()
+            .all(|kinds| infer_types(logic, &self.vm.skills, kinds).is_ok())
+    }
 
-                        println!(
-                            "  [SUCCESS] Concept: Opcode {} | Len: {}",
-                            skill_id, current_len
-                        );
-                        self.imprint_skill(skill_id, &input);
+    /// A random program of up to `max_program_len` words, ending in `Halt`.
+    fn random_genome(&mut self, depth: usize) -> Vec<f64> {
+        let len = self.rng.gen_range(1..=self.max_program_len);
+        let skills = self.vm.skills.skills_fitting_depth(depth);
+        let mut genome = Hypothesis::generate(len, &skills).logic;
+        genome.push(Op::Halt.as_f64());
+        genome
+    }
 
-                        // Construct the optimized return program
-                        let mut optimized = current_program[..logic_start].to_vec();
-                        optimized.push(skill_id as f64);
-                        optimized.push(Op::Halt.as_f64());
-                        return Some(optimized);
+    /// Evolves a population of programs. Each generation keeps the elites,
+    /// breeds the rest from tournament winners and mixes in fresh programs;
+    /// a child identical to one already in the generation is mutated or
+    /// replaced, so the population cannot collapse onto one program.
+    fn search_genetic(
+        &mut self,
+        monitor: &mut Monitor,
+        examples: &[(Vec<UVal>, Vec<UVal>)],
+        holdout: &[(Vec<UVal>, Vec<UVal>)],
+        all_examples: &[(Vec<UVal>, Vec<UVal>)],
+        attempts_limit: usize,
+    ) -> SynthesisReport {
+        let config = self.genetic;
+        let size = config.population.max(2);
+        let depth = examples[0].0.len();
+        let input_kinds = Self::input_kinds(all_examples);
+        let budget = attempts_limit.saturating_mul(self.max_program_len);
+        let bits = |genome: &[f64]| genome.iter().map(|f| f.to_bits()).collect::<Vec<u64>>();
+
+        let mut best_rejected: Option<SynthesisReport> = None;
+        // Programs that solved the training set but failed validation; they
+        // score zero so they cannot take over the population.
+        let mut rejected: HashSet<Vec<u64>> = HashSet::new();
+        // Skeletons with constant holes, by the filling found for them.
+        let mut solved: HashMap<Vec<u64>, Vec<f64>> = HashMap::new();
+        let mut best: Option<(Vec<f64>, f64)> = None;
+        let mut population: Vec<Vec<f64>> = (0..size).map(|_| self.random_genome(depth)).collect();
+        let mut evaluated = 0;
+        let mut generation = 0;
+
+        'search: while evaluated < budget {
+            generation += 1;
+            let mut fitness = Vec::with_capacity(size);
+            for genome in population.iter_mut() {
+                if !monitor.tick(genome.len()) {
+                    break 'search;
+                }
+                evaluated += 1;
+                let logic = genome.clone();
+                // Selection needs repeatable scores, so programs that touch
+                // state outside the stack are not bred.
+                if rejected.contains(&bits(&logic))
+                    || !is_pure(&logic, &self.vm.skills)
+                    || !self.well_typed(&logic, &input_kinds)
+                {
+                    fitness.push(0.0);
+                    continue;
+                }
+                // Elites and repeated offspring keep their skeletons, so
+                // each one is solved for once.
+                let logic = if constants::holes(&logic).is_empty() {
+                    logic
+                } else if let Some(filled) = solved.get(&bits(&logic)) {
+                    filled.clone()
+                } else {
+                    let before = monitor.evaluations();
+                    let filled = self.solve_constants(monitor, &logic, examples);
+                    evaluated += monitor.evaluations() - before;
+                    solved.insert(bits(&logic), filled.clone());
+                    filled
+                };
+                genome.clone_from(&logic);
+                let (score, solved_all) = self.evaluate_logic_on_examples(&logic, examples);
+                if solved_all {
+                    let report = self.score_program(&logic, examples, holdout);
+                    if report.generalizes() {
+                        let len = instruction_starts(&logic).len() - 1;
+                        monitor.report(SearchEvent::Evolved {
+                            generations: generation,
+                            solved: true,
+                        });
+                        return self.promote(monitor, &logic, "GP", len, all_examples, report);
+                    }
+                    Self::reject(
+                        monitor,
+                        &mut best_rejected,
+                        report,
+                        examples.len(),
+                        holdout.len(),
+                    );
+                    if self.stop_at_counterexample {
+                        break 'search;
                     }
-                    return Some(current_program);
+                    rejected.insert(bits(&logic));
+                    fitness.push(0.0);
+                    continue;
+                }
+                if best.as_ref().is_none_or(|(_, f)| score > *f) {
+                    monitor.improve(score, logic.len(), "GP");
+                    best = Some((logic, score));
+                }
+                fitness.push(score);
+            }
+            let lengths: Vec<usize> = population.iter().map(Vec::len).collect();
+
+            let mut order: Vec<usize> = (0..size).collect();
+            order.sort_by(|a, b| {
+                fitness[*b]
+                    .total_cmp(&fitness[*a])
+                    .then(lengths[*a].cmp(&lengths[*b]))
+            });
+            // Fitness sharing: programs with the same score almost always
+            // compute the same thing, so they split it between them and a
+            // crowded niche stops drowning out the rest of the population.
+            let mut niches: HashMap<u64, usize> = HashMap::new();
+            for score in &fitness {
+                *niches.entry(score.to_bits()).or_default() += 1;
+            }
+            let shared: Vec<f64> = fitness
+                .iter()
+                .map(|score| score / niches[&score.to_bits()] as f64)
+                .collect();
+
+            let mut seen: HashSet<Vec<u64>> = HashSet::new();
+            let mut next: Vec<Vec<f64>> = Vec::with_capacity(size);
+            for &i in &order {
+                if next.len() >= config.elites {
+                    break;
+                }
+                if seen.insert(bits(&population[i])) {
+                    next.push(population[i].clone());
                 }
             }
+
+            let immigrants = (size as f64 * config.immigrant_rate).round() as usize;
+            let bred = size.saturating_sub(immigrants).max(next.len());
+            while next.len() < bred {
+                let a =
+                    &population[tournament(&shared, &lengths, config.tournament, &mut self.rng)];
+                let mut child = if self.rng.gen_bool(config.crossover_rate) {
+                    let b = &population
+                        [tournament(&shared, &lengths, config.tournament, &mut self.rng)];
+                    let mut child = crossover(&a[..a.len() - 1], &b[..b.len() - 1], &mut self.rng);
+                    child.push(Op::Halt.as_f64());
+                    child
+                } else {
+                    a.clone()
+                };
+                if child.len() > self.max_program_len + 1 {
+                    child = a.clone();
+                }
+                if self.rng.gen_bool(config.mutation_rate) {
+                    self.mutate_program(&mut child, 0);
+                }
+                // Diversity: a duplicate gets one more mutation, then gives
+                // way to a fresh program.
+                if seen.contains(&bits(&child)) {
+                    self.mutate_program(&mut child, 0);
+                }
+                if !seen.insert(bits(&child)) {
+                    child = self.random_genome(depth);
+                    seen.insert(bits(&child));
+                }
+                next.push(child);
+            }
+            while next.len() < size {
+                next.push(self.random_genome(depth));
+            }
+            population = next;
+        }
+
+        monitor.report(SearchEvent::Evolved {
+            generations: generation,
+            solved: false,
+        });
+        match (best_rejected, best) {
+            (Some(report), _) => report,
+            (None, Some((program, _))) => self.score_program(&program, examples, holdout),
+            (None, None) => SynthesisReport::default(),
         }
-        None
     }
 
-    fn register_or_find_skill(&mut self, logic: Vec<f64>) -> i64 {
+    /// Enumerates straight-line programs shortest first, accepting the first
+    /// one that also solves `holdout`.
+    fn search_enumerative(
+        &mut self,
+        monitor: &mut Monitor,
+        examples: &[(Vec<UVal>, Vec<UVal>)],
+        holdout: &[(Vec<UVal>, Vec<UVal>)],
+        all_examples: &[(Vec<UVal>, Vec<UVal>)],
+        attempts_limit: usize,
+    ) -> SynthesisReport {
+        // Both callbacks below report to the monitor.
+        let monitor = RefCell::new(monitor);
+        let mut enumerator = Enumerator::new(&self.vm.skills);
+        let mut best_rejected: Option<SynthesisReport> = None;
+        let mut accepted: Option<SynthesisReport> = None;
+        let budget = attempts_limit.saturating_mul(self.max_program_len);
+        let stop_at_counterexample = self.stop_at_counterexample;
+        let unlabelled: Vec<Vec<UVal>> = holdout.iter().map(|(input, _)| input.clone()).collect();
+        let found = enumerator.search(
+            examples,
+            &unlabelled,
+            self.max_program_len,
+            budget,
+            |logic| {
+                let mut program = logic.to_vec();
+                program.push(Op::Halt.as_f64());
+                let report = self.score_program(&program, examples, holdout);
+                if report.generalizes() {
+                    accepted = Some(report);
+                    true
+                } else {
+                    Self::reject(
+                        &mut monitor.borrow_mut(),
+                        &mut best_rejected,
+                        report,
+                        examples.len(),
+                        holdout.len(),
+                    );
+                    stop_at_counterexample
+                }
+            },
+            |stats| !monitor.borrow_mut().tick(stats.depth + 1),
+        );
+
+        monitor
+            .borrow_mut()
+            .report(SearchEvent::Enumerated(enumerator.stats));
+        match (found, accepted) {
+            (Some(logic), Some(report)) => {
+                let mut program = logic;
+                program.push(Op::Halt.as_f64());
+                let len = instruction_starts(&program).len() - 1;
+                self.promote(
+                    &mut monitor.borrow_mut(),
+                    &program,
+                    "ENUM",
+                    len,
+                    all_examples,
+                    report,
+                )
+            }
+            _ => best_rejected.unwrap_or_default(),
+        }
+    }
+
+    /// Keeps the rejected candidate with the best validation accuracy.
+    fn reject(
+        monitor: &mut Monitor,
+        best_rejected: &mut Option<SynthesisReport>,
+        report: SynthesisReport,
+        train_len: usize,
+        holdout_len: usize,
+    ) {
+        monitor.report(SearchEvent::Rejected {
+            solved: train_len,
+            failed: report.failures.len(),
+            validation: holdout_len,
+        });
+        if best_rejected
+            .as_ref()
+            .is_none_or(|best| best.validation_accuracy < report.validation_accuracy)
+        {
+            *best_rejected = Some(report);
+        }
+    }
+
+    /// Prunes a program that generalized, registers its logic as a skill
+    /// and imprints it. `report` is the program's validation report.
+    fn promote(
+        &mut self,
+        monitor: &mut Monitor,
+        current_program: &[f64],
+        strategy: &str,
+        current_len: usize,
+        all_examples: &[(Vec<UVal>, Vec<UVal>)],
+        report: SynthesisReport,
+    ) -> SynthesisReport {
+        let mut clean_logic = current_program.to_vec();
+        if clean_logic.last() == Some(&(Op::Halt.as_f64())) {
+            clean_logic.pop();
+        }
+
+        // Pruning must keep every example (held-out ones
+        // included) exact, not just the first.
+        use crate::hypothesis::Pruner;
+        let pruned_logic = Pruner::prune(&self.vm, &clean_logic, all_examples);
+
+        if pruned_logic.is_empty() {
+            return SynthesisReport {
+                strategy: Some(strategy.to_string()),
+                ..report
+            };
+        }
+        // Not registrable: hand back the solved program as it is.
+        let Some(skill_id) = self.register_or_find_skill(
+            pruned_logic.clone(),
+            SkillInfo::synthesized(strategy, all_examples),
+        ) else {
+            return SynthesisReport {
+                strategy: Some(strategy.to_string()),
+                ..report
+            };
+        };
+
+        monitor.report(SearchEvent::Promoted {
+            skill: skill_id,
+            len: current_len,
+            before: clean_logic.len(),
+            after: pruned_logic.len(),
+        });
+        self.imprint_skill(skill_id, all_examples);
+
+        SynthesisReport {
+            program: Some(vec![skill_id as f64, Op::Halt.as_f64()]),
+            skill: (skill_id >= SKILL_OPCODE_BASE).then_some(skill_id),
+            strategy: Some(strategy.to_string()),
+            ..report
+        }
+    }
+
+    /// Runs `program` on both example sets and reports how it did, without
+    /// promoting anything.
+    fn score_program(
+        &mut self,
+        program: &[f64],
+        train: &[(Vec<UVal>, Vec<UVal>)],
+        validation: &[(Vec<UVal>, Vec<UVal>)],
+    ) -> SynthesisReport {
+        let solved = self
+            .run_on_examples(program, train)
+            .iter()
+            .zip(train)
+            .filter(|(got, (_, expected))| got == &expected)
+            .count();
+
+        let mut failures = Vec::new();
+        for (got, (input, expected)) in self
+            .run_on_examples(program, validation)
+            .into_iter()
+            .zip(validation)
+        {
+            if got != *expected {
+                failures.push(ValidationFailure {
+                    input: input.clone(),
+                    expected: expected.clone(),
+                    got,
+                });
+            }
+        }
+
+        SynthesisReport {
+            program: Some(program.to_vec()),
+            skill: None,
+            strategy: None,
+            evaluations: 0,
+            stopped: None,
+            train_accuracy: solved as f64 / train.len().max(1) as f64,
+            validation_accuracy: (!validation.is_empty())
+                .then(|| 1.0 - failures.len() as f64 / validation.len() as f64),
+            failures,
+        }
+    }
+
+    /// Id of an existing skill with this logic, or of a newly defined one.
+    /// `None` if the logic cannot be defined as a skill.
+    fn register_or_find_skill(&mut self, logic: Vec<f64>, info: SkillInfo) -> Option<i64> {
         // [FIX] Prevent Looping/Aliasing:
         // If the new skill logic is just a single instruction, return that instruction's ID.
         if logic.len() == 1 {
-            return logic[0] as i64;
+            return Some(logic[0] as i64);
         }
 
         for (id, macro_logic) in &self.vm.skills.macros {
             if *macro_logic == logic {
-                return *id;
+                return Some(*id);
             }
         }
-        let new_id = self.generate_random_id();
-        self.vm.skills.define_skill(new_id, logic);
-        new_id
+        // Behaves like an existing skill on every probe and every example?
+        // Reuse that one.
+        if let Some(id) = self.find_equivalent_skill(&logic, &info.examples) {
+            return Some(id);
+        }
+        let new_id = self.vm.skills.allocate_id()?;
+        self.vm
+            .skills
+            .define_skill_with_info(new_id, logic, info)
+            .ok()?;
+        Some(new_id)
     }
 
-    fn generate_smart_skill_logic(&mut self, target_len: usize) -> i64 {
-        let mut logic = Vec::new();
-        for _ in 0..target_len {
-            let op = self.choose_random_op_with_bias(2);
-            logic.push(op as f64);
+    /// A skill that matches `logic` on every probe and reproduces every one
+    /// of `examples` exactly. Probes alone cannot tell `Literal(42) Eq` from
+    /// `Literal(43) Eq`, so a match is only reused once it is checked on the
+    /// examples `logic` was accepted for.
+    fn find_equivalent_skill(
+        &mut self,
+        logic: &[f64],
+        examples: &[(Vec<UVal>, Vec<UVal>)],
+    ) -> Option<i64> {
+        let skills = &self.vm.skills;
+        let prober = self.prober.get_or_insert_with(|| Prober::new(skills));
+        prober.sync(skills);
+        let target = prober.fingerprint(logic)?;
+
+        self.fingerprints
+            .retain(|id, (body, _)| skills.get_skill(*id) == Some(body));
+        let mut matches = Vec::new();
+        for id in skills.ids() {
+            let fingerprint = match self.fingerprints.get(&id) {
+                Some((_, fingerprint)) => *fingerprint,
+                None => {
+                    let fingerprint = prober.fingerprint_skill(id);
+                    self.fingerprints
+                        .insert(id, (skills.get_skill(id)?.clone(), fingerprint));
+                    fingerprint
+                }
+            };
+            if fingerprint == Some(target) {
+                matches.push(id);
+            }
         }
-        self.register_or_find_skill(logic)
+        matches
+            .into_iter()
+            .find(|id| self.solves(&[*id as f64], examples))
     }
 
-    fn log_logic(&self, depth: usize, level: usize, strategy: &str, logic: &[f64], fitness: f64) {
-        let decoded: Vec<String> = logic
-            .iter()
-            .map(|&op| {
-                if op == Op::Add.as_f64() {
-                    "ADD".into()
-                } else if op == Op::Sub.as_f64() {
-                    "SUB".into()
-                } else if op == Op::Mul.as_f64() {
-                    "MUL".into()
-                } else if op == Op::Halt.as_f64() {
-                    "HALT".into()
-                } else if op >= SKILL_OPCODE_BASE as f64 {
-                    format!("OP_{}", op as i64)
-                } else {
-                    format!("LIT({})", op)
+    /// Appends the scored candidate to the trace log set by
+    /// `with_trace_log`, one line each. A failed write turns tracing off.
+    fn log_logic(
+        &mut self,
+        depth: usize,
+        level: usize,
+        strategy: &str,
+        logic: &[f64],
+        fitness: f64,
+    ) {
+        let Some(file) = self.trace.as_mut() else {
+            return;
+        };
+        let decoded: Vec<String> = instruction_starts(logic)
+            .into_iter()
+            .map(|idx| {
+                let raw = logic[idx];
+                let op = raw.is_finite().then(|| Op::from_i64(raw as i64)).flatten();
+                match op {
+                    _ if raw >= SKILL_OPCODE_BASE as f64 => format!("OP_{}", raw as i64),
+                    Some(Op::Literal) => {
+                        format!("LIT({})", logic.get(idx + 1).unwrap_or(&f64::NAN))
+                    }
+                    Some(op) if has_operand(op) => format!(
+                        "{}({})",
+                        format!("{:?}", op).to_uppercase(),
+                        logic.get(idx + 1).unwrap_or(&f64::NAN)
+                    ),
+                    Some(op) => format!("{:?}", op).to_uppercase(),
+                    None => format!("?({})", raw),
                 }
             })
             .collect();
-        // Commenting out logging for performance
-        /*
-        let mut file = OpenOptions::new().create(true).append(true).open("text.txt").unwrap();
-        writeln!(
-            file,
-            "[{}/{}] [Strategy: {}] Fit: {:.4} | Logic: {:?}",
-            depth,
-            level,
-            strategy,
-            fitness,
-            decoded
-        ).unwrap();
-        */
-    }
-
-    fn speculate_new_skill(&mut self, program: &mut Vec<f64>, logic_start: usize) -> Option<i64> {
-        let logic_len = program.len().saturating_sub(1).saturating_sub(logic_start);
+        // One write per line, so workers sharing the file do not
+        // interleave within a line.
+        let line = format!(
+            "[{}/{}] [Strategy: {}] Fit: {:.4} | Logic: {:?}\n",
+            depth, level, strategy, fitness, decoded
+        );
+        if file.write_all(line.as_bytes()).is_err() {
+            self.trace = None;
+        }
+    }
+
+    pub(crate) fn speculate_new_skill(
+        &mut self,
+        program: &mut Vec<f64>,
+        logic_start: usize,
+    ) -> Option<i64> {
+        // Work on whole instructions; the trailing HALT is never extracted.
+        let starts = instruction_starts(program);
+        let first = starts.iter().position(|&idx| idx >= logic_start)?;
+        let mut last = starts.len();
+        if program.last() == Some(&Op::Halt.as_f64()) && starts.last() == Some(&(program.len() - 1))
+        {
+            last -= 1;
+        }
+        let logic_len = last.saturating_sub(first);
         if logic_len < 2 {
             return None;
         }
         let window_size = self.rng.gen_range(2..=std::cmp::min(5, logic_len));
-        let max_start = (program.len() - 1).saturating_sub(window_size);
-        if max_start < logic_start {
-            return None;
-        }
-        let start_idx = self.rng.gen_range(logic_start..=max_start);
-        let pattern = program[start_idx..start_idx + window_size].to_vec();
-        let new_id = self.register_or_find_skill(pattern);
-        program.drain(start_idx..start_idx + window_size);
-        program.insert(start_idx, new_id as f64);
+        let start = self.rng.gen_range(first..=last - window_size);
+        // Windows that jumps enter or leave cannot become a skill.
+        let extraction = extract_window(program, start..start + window_size)?;
+        let new_id =
+            self.register_or_find_skill(extraction.body, SkillInfo::synthesized("SPEC", &[]))?;
+        *program = extraction.program;
+        program[extraction.call_index] = new_id as f64;
         Some(new_id)
     }
 
-    fn mutate_program(&mut self, program: &mut Vec<f64>, logic_start: usize) {
-        if program.len() <= logic_start + 1 {
+    /// The random source shared by the search and its strategies.
+    pub(crate) fn rng(&mut self) -> &mut impl Rng {
+        &mut self.rng
+    }
+
+    /// Swaps two instructions or replaces one. A `Literal` is never
+    /// replaced: its operand becomes a hole again so the constant is
+    /// re-solved, and a new `Literal` takes over the following instruction's
+    /// word for its hole.
+    pub(crate) fn mutate_program(&mut self, program: &mut [f64], logic_start: usize) {
+        let end = program.len().saturating_sub(1);
+        let starts: Vec<usize> = instruction_starts(program)
+            .into_iter()
+            .filter(|&idx| idx >= logic_start && idx < end)
+            .collect();
+        if starts.is_empty() {
             return;
         }
-        let mutable_range = logic_start..program.len().saturating_sub(1);
-        if mutable_range.is_empty() {
+        let pick = self.rng.gen_range(0..starts.len());
+        let idx = starts[pick];
+        let single = |idx: usize| starts.binary_search(&(idx + 1)).is_ok() || idx + 1 == end;
+
+        if program[idx] == Op::Literal.as_f64() {
+            program[idx + 1] = HOLE;
             return;
         }
-        // [FIX] Clone the range because gen_range consumes it (Range is not Copy)
-        let idx = self.rng.gen_range(mutable_range.clone());
-        if self.rng.gen_bool(0.5) && program.len() > logic_start + 2 {
-            let swap_idx = self.rng.gen_range(mutable_range.clone());
-            program.swap(idx, swap_idx);
+        if self.rng.gen_bool(0.5) && starts.len() > 1 {
+            let swap_idx = starts[self.rng.gen_range(0..starts.len())];
+            if single(idx) && single(swap_idx) {
+                program.swap(idx, swap_idx);
+            }
         } else {
             let op = self.choose_random_op_with_bias(program.len().saturating_sub(logic_start));
-            program[idx] = op as f64;
+            if op != Op::Literal.as_i64() {
+                program[idx] = op as f64;
+            } else if single(idx) && starts.get(pick + 1).is_some_and(|&next| single(next)) {
+                program[idx] = op as f64;
+                program[idx + 1] = HOLE;
+            }
         }
     }
 
+    /// A program built by `build_program` from the biased op distribution.
+    pub(crate) fn random_build(
+        &mut self,
+        input: &[UVal],
+        target_len: usize,
+        shape_id: u8,
+    ) -> Vec<f64> {
+        self.build_program(input, target_len, true, shape_id);
+        self.program_buf.clone()
+    }
+
     fn build_program(
         &mut self,
         input: &[UVal],
@@ -361,6 +1454,9 @@ impl Trainer {
             };
 
             self.program_buf.push(op as f64);
+            if op == Op::Literal.as_i64() {
+                self.program_buf.push(HOLE);
+            }
 
             // Update History (Keep last 3)
             history.push(op);
@@ -420,9 +1516,7 @@ impl Trainer {
             Op::Reward.as_i64(),
             Op::Evolve.as_i64(),
         ];
-        for &custom_op in self.vm.skills.macros.keys() {
-            ops.push(custom_op);
-        }
+        ops.extend(self.vm.skills.skills_fitting_depth(stack_depth));
 
         if let Ok(mem) = self.vm.plasticity.memory.read() {
             let mut best_op = ops[0];
@@ -450,16 +1544,16 @@ impl Trainer {
 
                 // [FIX] Correct Nested Map Access
                 let mut weight = 0.0;
-                if let Some(targets) = mem.weights.get(&norm_last_event) {
-                    if let Some(w) = targets.get(&target) {
-                        weight = *w;
-                    }
+                if let Some(targets) = mem.weights.get(&norm_last_event)
+                    && let Some(w) = targets.get(&target)
+                {
+                    weight = *w;
                 }
 
-                if let Some(ctx_targets) = mem.weights.get(&Event::Context(shape_id)) {
-                    if let Some(ctx_w) = ctx_targets.get(&target) {
-                        weight += *ctx_w * 0.35;
-                    }
+                if let Some(ctx_targets) = mem.weights.get(&Event::Context(shape_id))
+                    && let Some(ctx_w) = ctx_targets.get(&target)
+                {
+                    weight += *ctx_w * 0.35;
                 }
 
                 if shape_id == 5 && op == Op::Parse.as_i64() {
@@ -492,12 +1586,11 @@ impl Trainer {
         ops[0]
     }
 
-    fn choose_random_op_with_bias(&mut self, stack_depth: usize) -> i64 {
-        if !self.vm.skills.macros.is_empty() && self.rng.gen_bool(0.3) {
-            let keys: Vec<_> = self.vm.skills.macros.keys().cloned().collect();
-            if let Some(id) = keys.get(self.rng.gen_range(0..keys.len())) {
-                return *id;
-            }
+    pub(crate) fn choose_random_op_with_bias(&mut self, stack_depth: usize) -> i64 {
+        // Skip skills that would underflow the current stack
+        let keys = self.vm.skills.skills_fitting_depth(stack_depth);
+        if !keys.is_empty() && self.rng.gen_bool(0.3) {
+            return keys[self.rng.gen_range(0..keys.len())];
         }
         let stack_favor = [
             Op::Add.as_i64(),
@@ -551,6 +1644,81 @@ impl Trainer {
         pool[self.rng.gen_range(0..pool.len())]
     }
 
+    /// Fills the constant holes of `logic` (see `constants`) from the
+    /// examples: a linear fit first, then small integers. Returns the first
+    /// filling that solves every example, else the fitted one, else every
+    /// hole at one. Programs that touch state outside the stack are not
+    /// solved for, as every probe would change what the next one sees.
+    /// Every trial filling is charged to `monitor`, and the search for one
+    /// stops with the fallback once the run has to stop.
+    fn solve_constants(
+        &mut self,
+        monitor: &mut Monitor,
+        logic: &[f64],
+        examples: &[(Vec<UVal>, Vec<UVal>)],
+    ) -> Vec<f64> {
+        let holes = constants::holes(logic);
+        if holes.is_empty() {
+            return logic.to_vec();
+        }
+        let mut fallback = constants::fill(logic, &holes, &vec![1.0; holes.len()]);
+        if !is_pure(logic, &self.vm.skills) {
+            return fallback;
+        }
+
+        if holes.len() <= MAX_FIT_HOLES {
+            let mut probes: Vec<Vec<Vec<UVal>>> = Vec::with_capacity(holes.len() + 1);
+            for unit in 0..=holes.len() {
+                if !monitor.charge() {
+                    return fallback;
+                }
+                let mut values = vec![0.0; holes.len()];
+                if unit > 0 {
+                    values[unit - 1] = 1.0;
+                }
+                probes
+                    .push(self.run_on_examples(&constants::fill(logic, &holes, &values), examples));
+            }
+            let expected: Vec<Vec<UVal>> = examples.iter().map(|(_, out)| out.clone()).collect();
+            if let Some(values) = constants::linear_fit(&probes, &expected) {
+                // Integer constants are the common case and survive
+                // round-off in the fit.
+                let rounded: Vec<f64> = values.iter().map(|v| v.round()).collect();
+                for candidate in [&rounded, &values] {
+                    if !monitor.charge() {
+                        return fallback;
+                    }
+                    let filled = constants::fill(logic, &holes, candidate);
+                    if self.solves(&filled, examples) {
+                        return filled;
+                    }
+                }
+                fallback = constants::fill(logic, &holes, &rounded);
+            }
+        }
+
+        if holes.len() <= MAX_ENUM_HOLES {
+            for values in constants::assignments(holes.len()) {
+                if !monitor.charge() {
+                    break;
+                }
+                let filled = constants::fill(logic, &holes, &values);
+                if self.solves(&filled, examples) {
+                    return filled;
+                }
+            }
+        }
+        fallback
+    }
+
+    /// True if `logic` leaves exactly the expected stack on every example,
+    /// stopping at the first one it gets wrong.
+    fn solves(&mut self, logic: &[f64], examples: &[(Vec<UVal>, Vec<UVal>)]) -> bool {
+        examples.iter().all(|example| {
+            self.run_on_examples(logic, std::slice::from_ref(example))[0] == example.1
+        })
+    }
+
     fn evaluate_logic_on_examples(
         &mut self,
         logic: &[f64],
@@ -559,17 +1727,12 @@ impl Trainer {
         let mut total = 0.0;
         let mut solved_all = true;
 
-        for (input, expected) in examples {
-            let mut program = self.materialize_program(input, logic);
-            self.vm.stack.clear();
-            for v in input {
-                self.vm.stack.push(v.clone());
-            }
-            let result = self.execute_program(&mut program);
-            let fitness = self.calculate_fitness(&result, expected);
-            total += fitness;
+        for (result, (_, expected)) in self.run_on_examples(logic, examples).iter().zip(examples) {
+            total += self.calculate_fitness(result, expected);
 
-            if fitness < 0.9999 {
+            // Fitness is a gradient for the search; solving means the exact
+            // stack, so `Bool(true)` never passes for a truthy number.
+            if result != expected {
                 solved_all = false;
             }
         }
@@ -577,6 +1740,23 @@ impl Trainer {
         (total / examples.len() as f64, solved_all)
     }
 
+    /// Final stack of `logic` on each example's input.
+    fn run_on_examples(
+        &mut self,
+        logic: &[f64],
+        examples: &[(Vec<UVal>, Vec<UVal>)],
+    ) -> Vec<Vec<UVal>> {
+        examples
+            .iter()
+            .map(|(input, _)| {
+                let mut program = self.materialize_program(input, logic);
+                self.vm.stack.clear();
+                self.vm.stack.extend(input.iter().cloned());
+                self.execute_program(&mut program)
+            })
+            .collect()
+    }
+
     fn materialize_program(&self, _input: &[UVal], logic: &[f64]) -> Vec<f64> {
         let mut program = logic.to_vec();
         if program.last() != Some(&Op::Halt.as_f64()) {
@@ -585,57 +1765,37 @@ impl Trainer {
         program
     }
 
-    fn imprint_skill(&self, op_id: i64, sample_input: &[UVal]) {
-        if let Ok(mut mem) = self.vm.plasticity.memory.write() {
-            // [FIX] NORMALIZE: Save the skill as applicable to any "deep enough" stack
-            let norm_depth = Self::normalize_depth(sample_input.len());
-
-            let context = Event::Opcode {
-                opcode: Op::Literal.as_i64(),
-                stack_depth: norm_depth,
-            };
-            let target = Event::Opcode {
-                opcode: op_id,
-                stack_depth: norm_depth,
-            };
+    /// Links the skill to every (normalized) input depth it was shown to
+    /// work at, not just the first example's.
+    fn imprint_skill(&self, op_id: i64, examples: &[(Vec<UVal>, Vec<UVal>)]) {
+        let mut depths: Vec<usize> = examples
+            .iter()
+            .map(|(input, _)| Self::normalize_depth(input.len()))
+            .collect();
+        depths.sort_unstable();
+        depths.dedup();
 
-            mem.weights
-                .entry(context)
-                .or_insert_with(std::collections::HashMap::new)
-                .insert(target, 10.0);
-        }
-    }
+        if let Ok(mut mem) = self.vm.plasticity.memory.write() {
+            for norm_depth in depths {
+                let context = Event::Opcode {
+                    opcode: Op::Literal.as_i64(),
+                    stack_depth: norm_depth,
+                };
+                let target = Event::Opcode {
+                    opcode: op_id,
+                    stack_depth: norm_depth,
+                };
 
-    fn generate_random_id(&mut self) -> i64 {
-        loop {
-            let id = self.rng.gen_range(1000..9999);
-            if !self.vm.skills.macros.contains_key(&id) {
-                return id;
+                mem.weights
+                    .entry(context)
+                    .or_insert_with(std::collections::HashMap::new)
+                    .insert(target, 10.0);
             }
         }
     }
 
     fn calculate_fitness(&self, result: &[UVal], expected: &[UVal]) -> f64 {
-        if result.is_empty() || result.len() != expected.len() {
-            return 0.0;
-        }
-        let mut score = 0.0;
-
-        for (got, want) in result.iter().zip(expected.iter()) {
-            match (got, want) {
-                // Existing Number comparison
-                (UVal::Number(a), UVal::Number(b)) => {
-                    score += 1.0 / (1.0 + (a - b).abs());
-                }
-                // [FIX] Logic Comparison: Convert both to truthy booleans
-                _ => {
-                    if got.is_truthy() == want.is_truthy() {
-                        score += 1.0;
-                    }
-                }
-            }
-        }
-        score / expected.len() as f64
+        self.fitness.score(result, expected)
     }
 
     fn execute_program(&mut self, program: &mut Vec<f64>) -> Vec<UVal> {
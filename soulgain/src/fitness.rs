@@ -0,0 +1,163 @@
+use crate::types::UVal;
+
+/// Scores a candidate's final stack against the expected one.
+///
+/// Scores lie in `0.0..=1.0` and only guide the search: a candidate counts
+/// as a solution when its stack matches exactly, whatever its score.
+pub trait Fitness: Send + Sync {
+    fn name(&self) -> &str;
+
+    fn score(&self, result: &[UVal], expected: &[UVal]) -> f64;
+}
+
+/// 1.0 for the exact stack, 0.0 for anything else.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Exact;
+
+impl Fitness for Exact {
+    fn name(&self) -> &str {
+        "exact"
+    }
+
+    fn score(&self, result: &[UVal], expected: &[UVal]) -> f64 {
+        if result == expected { 1.0 } else { 0.0 }
+    }
+}
+
+/// Compares slot by slot, strings by their character edit distance and
+/// every other value by equality.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EditDistance;
+
+impl Fitness for EditDistance {
+    fn name(&self) -> &str {
+        "edit-distance"
+    }
+
+    fn score(&self, result: &[UVal], expected: &[UVal]) -> f64 {
+        positional(result, expected, |got, want| match (got, want) {
+            (UVal::String(a), UVal::String(b)) => string_similarity(a, b),
+            _ => equal(got, want),
+        })
+    }
+}
+
+/// Partial credit that respects value kinds: numbers by distance, strings
+/// by edit distance, everything else by equality. A value of the wrong
+/// kind earns nothing. The default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TypeAware;
+
+impl Fitness for TypeAware {
+    fn name(&self) -> &str {
+        "type-aware"
+    }
+
+    fn score(&self, result: &[UVal], expected: &[UVal]) -> f64 {
+        positional(result, expected, typed_similarity)
+    }
+}
+
+/// Order-insensitive: the fraction of expected values present somewhere
+/// in the result, each result value used at most once.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Multiset;
+
+impl Fitness for Multiset {
+    fn name(&self) -> &str {
+        "multiset"
+    }
+
+    fn score(&self, result: &[UVal], expected: &[UVal]) -> f64 {
+        let longest = result.len().max(expected.len());
+        if longest == 0 {
+            return 1.0;
+        }
+        let mut unused: Vec<&UVal> = result.iter().collect();
+        let mut matched = 0;
+        for want in expected {
+            if let Some(pos) = unused.iter().position(|got| *got == want) {
+                unused.swap_remove(pos);
+                matched += 1;
+            }
+        }
+        matched as f64 / longest as f64
+    }
+}
+
+/// Type-aware credit for the top of the stack alone; whatever lies
+/// beneath it is ignored.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TopOfStack;
+
+impl Fitness for TopOfStack {
+    fn name(&self) -> &str {
+        "top-of-stack"
+    }
+
+    fn score(&self, result: &[UVal], expected: &[UVal]) -> f64 {
+        match (result.last(), expected.last()) {
+            (Some(got), Some(want)) => typed_similarity(got, want),
+            (None, None) => 1.0,
+            _ => 0.0,
+        }
+    }
+}
+
+/// Averages `slot` over the stacks aligned at the top. Slots only one stack
+/// has score zero, so a missing or extra value costs a share of the score
+/// rather than all of it.
+fn positional(result: &[UVal], expected: &[UVal], slot: impl Fn(&UVal, &UVal) -> f64) -> f64 {
+    let longest = result.len().max(expected.len());
+    if longest == 0 {
+        return 1.0;
+    }
+    let total: f64 = result
+        .iter()
+        .rev()
+        .zip(expected.iter().rev())
+        .map(|(got, want)| slot(got, want))
+        .sum();
+    total / longest as f64
+}
+
+fn equal(got: &UVal, want: &UVal) -> f64 {
+    if got == want { 1.0 } else { 0.0 }
+}
+
+fn typed_similarity(got: &UVal, want: &UVal) -> f64 {
+    match (got, want) {
+        (UVal::Number(a), UVal::Number(b)) => {
+            if a == b {
+                1.0
+            } else if a.is_finite() && b.is_finite() {
+                1.0 / (1.0 + (a - b).abs())
+            } else {
+                0.0
+            }
+        }
+        (UVal::String(a), UVal::String(b)) => string_similarity(a, b),
+        _ => equal(got, want),
+    }
+}
+
+/// 1.0 minus the Levenshtein distance scaled by the longer string.
+fn string_similarity(a: &str, b: &str) -> f64 {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let longest = a.len().max(b.len());
+    if longest == 0 {
+        return 1.0;
+    }
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.iter().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    1.0 - row[b.len()] as f64 / longest as f64
+}
@@ -0,0 +1,78 @@
+use rand::Rng;
+
+use crate::logic::instruction_starts;
+
+/// Knobs for `SearchMode::Genetic`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GeneticConfig {
+    pub population: usize,
+    /// Individuals drawn per tournament; the fittest one becomes a parent.
+    pub tournament: usize,
+    /// Fittest distinct individuals copied unchanged into the next generation.
+    pub elites: usize,
+    /// Chance a child is bred by crossover rather than cloned from a parent.
+    pub crossover_rate: f64,
+    /// Chance a child is mutated after breeding.
+    pub mutation_rate: f64,
+    /// Fraction of each generation replaced by fresh random programs.
+    pub immigrant_rate: f64,
+}
+
+impl Default for GeneticConfig {
+    fn default() -> Self {
+        Self {
+            population: 200,
+            tournament: 5,
+            elites: 4,
+            crossover_rate: 0.7,
+            mutation_rate: 0.3,
+            immigrant_rate: 0.1,
+        }
+    }
+}
+
+/// Replaces a random run of instructions in `a` with a random run of
+/// instructions from `b`, so a child can combine a sub-computation from each
+/// parent. Cuts fall on instruction boundaries, so operands are never split.
+/// Both parents and the child are logic without a trailing `Halt`.
+pub fn crossover<R: Rng + ?Sized>(a: &[f64], b: &[f64], rng: &mut R) -> Vec<f64> {
+    let cuts = |logic: &[f64]| {
+        let mut cuts = instruction_starts(logic);
+        cuts.push(logic.len());
+        cuts
+    };
+    let (cuts_a, cuts_b) = (cuts(a), cuts(b));
+    let pick = |cuts: &[usize], rng: &mut R| {
+        let i = rng.gen_range(0..cuts.len());
+        let j = rng.gen_range(0..cuts.len());
+        (cuts[i.min(j)], cuts[i.max(j)])
+    };
+    let (a_start, a_end) = pick(&cuts_a, rng);
+    let (b_start, b_end) = pick(&cuts_b, rng);
+
+    let mut child = Vec::with_capacity(a.len() - (a_end - a_start) + (b_end - b_start));
+    child.extend_from_slice(&a[..a_start]);
+    child.extend_from_slice(&b[b_start..b_end]);
+    child.extend_from_slice(&a[a_end..]);
+    child
+}
+
+/// Index of the winner of a tournament of `size` random individuals. Higher
+/// fitness wins; ties go to the shorter program, which keeps bloat down.
+pub fn tournament<R: Rng + ?Sized>(
+    fitness: &[f64],
+    lengths: &[usize],
+    size: usize,
+    rng: &mut R,
+) -> usize {
+    let mut best = rng.gen_range(0..fitness.len());
+    for _ in 1..size.max(1) {
+        let other = rng.gen_range(0..fitness.len());
+        if fitness[other] > fitness[best]
+            || (fitness[other] == fitness[best] && lengths[other] < lengths[best])
+        {
+            best = other;
+        }
+    }
+    best
+}
@@ -1,3 +1,6 @@
+use crate::constants::HOLE;
+use crate::intuition::IntuitionEngine;
+use crate::logic::instruction_starts;
 use crate::types::UVal;
 use crate::vm::{Op, SoulGainVM};
 use rand::Rng;
@@ -46,16 +49,29 @@ impl Hypothesis {
             let remaining = target_len - logic.len();
 
             if remaining >= 6 && rng.gen_bool(0.12) {
-                // Compare & Swap template: [Over, Over, Gt, JmpIf(2), Swap]
+                // Compare & Swap template: [Over, Over, Gt, JmpIfRel(+1), Swap]
+                // The relative jump skips the Swap wherever the template lands.
                 logic.push(Op::Over.as_f64());
                 logic.push(Op::Over.as_f64());
                 logic.push(Op::Gt.as_f64());
-                logic.push(Op::JmpIf.as_f64());
-                logic.push(2.0);
+                logic.push(Op::JmpIfRel.as_f64());
+                logic.push(1.0);
                 logic.push(Op::Swap.as_f64());
                 continue;
             }
 
+            if remaining >= 2 && rng.gen_bool(0.15) {
+                // Constant template: [Literal, HOLE, (Add|Sub|Mul|Mod)]
+                // The trainer solves for the value before scoring.
+                logic.push(Op::Literal.as_f64());
+                logic.push(HOLE);
+                if remaining >= 3 {
+                    let arithmetic = [Op::Add, Op::Sub, Op::Mul, Op::Mod];
+                    logic.push(arithmetic[rng.gen_range(0..arithmetic.len())].as_f64());
+                }
+                continue;
+            }
+
             if remaining >= 1 && rng.gen_bool(0.12) {
                 // Type Cast template: [Parse]
                 logic.push(Op::Parse.as_f64());
@@ -78,28 +94,37 @@ impl Hypothesis {
 pub struct Pruner;
 
 impl Pruner {
-    /// Iteratively removes instructions to find the shortest valid logic sequence.
+    /// Iteratively removes instructions to find the shortest logic sequence
+    /// that still reproduces every example exactly.
     pub fn prune(
         base_vm: &SoulGainVM,
         original_logic: &[f64],
-        input: &[UVal],
-        expected: &[UVal],
+        examples: &[(Vec<UVal>, Vec<UVal>)],
     ) -> Vec<f64> {
+        // Create a lightweight VM for testing, with a clone of the brain
+        // (skills/memory) so the logic has context
+        let mut test_vm = SoulGainVM::new(Vec::new());
+        test_vm.skills = base_vm.skills.clone();
+        test_vm.plasticity = base_vm.plasticity.clone();
+
         let mut best_logic = original_logic.to_vec();
         let mut i = 0;
 
-        // Loop through the logic. Try to remove the instruction at index `i`.
-        while i < best_logic.len() {
+        // Loop through the logic. Try to remove the `i`th instruction,
+        // operand included.
+        loop {
+            let starts = instruction_starts(&best_logic);
             // Don't prune if we are down to 1 instruction
-            if best_logic.len() <= 1 {
+            if i >= starts.len() || starts.len() <= 1 {
                 break;
             }
 
+            let end = starts.get(i + 1).copied().unwrap_or(best_logic.len());
             let mut candidate = best_logic.clone();
-            candidate.remove(i);
+            candidate.drain(starts[i]..end);
 
             // Check if the shorter candidate still produces the EXACT expected output
-            if Self::validates(base_vm, &candidate, input, expected) {
+            if Self::validates(&mut test_vm, base_vm, &candidate, examples) {
                 // Success! The instruction was useless (Junk DNA).
                 // Keep the shorter version.
                 // We do NOT increment 'i' because the next instruction shifted into slot 'i'.
@@ -113,39 +138,33 @@ impl Pruner {
         best_logic
     }
 
-    fn validates(base_vm: &SoulGainVM, logic: &[f64], input: &[UVal], expected: &[UVal]) -> bool {
-        // Create a lightweight VM for testing
-        let mut test_vm = SoulGainVM::new(Vec::new());
-
-        // Clone the brain (skills/memory) so the logic has context
-        test_vm.skills = base_vm.skills.clone();
-        test_vm.memory = base_vm.memory.clone();
-        test_vm.plasticity = base_vm.plasticity.clone();
-
-        // Load Input
-        for val in input {
-            test_vm.stack.push(val.clone());
-        }
-
-        // Setup Program
-        test_vm.program = logic.to_vec();
-
-        // Ensure Halt exists for safety
-        if test_vm.program.last() != Some(&Op::Halt.as_f64()) {
-            test_vm.program.push(Op::Halt.as_f64());
-        }
+    /// True if `logic` leaves exactly the expected stack on every example.
+    /// Each example starts from `base_vm`'s skills and memory with fresh
+    /// counters, so side effects of one run never leak into the next.
+    fn validates(
+        test_vm: &mut SoulGainVM,
+        base_vm: &SoulGainVM,
+        logic: &[f64],
+        examples: &[(Vec<UVal>, Vec<UVal>)],
+    ) -> bool {
+        examples.iter().all(|(input, expected)| {
+            test_vm.reset_execution();
+            test_vm.reset_counters();
+            test_vm.memory = base_vm.memory.clone();
+            test_vm.skills.sync_from(&base_vm.skills);
+            test_vm.intuition = IntuitionEngine::default();
+            test_vm.stack = input.clone();
+
+            // Ensure Halt exists for safety
+            test_vm.program = logic.to_vec();
+            if test_vm.program.last() != Some(&Op::Halt.as_f64()) {
+                test_vm.program.push(Op::Halt.as_f64());
+            }
 
-        test_vm.run(5000); // Give it enough fuel
+            test_vm.run(5000); // Give it enough fuel
 
-        // Strict Check: Stack must match expected output EXACTLY
-        if test_vm.stack.len() != expected.len() {
-            return false;
-        }
-        for (a, b) in test_vm.stack.iter().zip(expected.iter()) {
-            if a != b {
-                return false;
-            }
-        }
-        true
+            // Strict Check: Stack must match expected output EXACTLY
+            test_vm.stack == *expected
+        })
     }
 }
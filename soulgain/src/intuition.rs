@@ -1,10 +1,31 @@
 use std::collections::{HashMap, VecDeque};
-
-use crate::types::UVal;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::persistence::{self, FNV_OFFSET, FNV_PRIME, SaveOptions};
+use crate::types::{SkillLibrary, UVal};
+
+/// Bumped whenever the persisted `IntuitionEngine` layout changes incompatibly.
+pub const INTUITION_STATE_VERSION: u32 = 1;
+
+/// Fields of a bare engine as version 1 bundles wrote it. An object with
+/// neither an envelope nor any of these is not intuition state.
+const BARE_ENGINE_FIELDS: [&str; 7] = [
+    "skill_meta",
+    "weights",
+    "gate_threshold",
+    "deterministic_mode",
+    "decay_tau_ticks",
+    "pending_credits",
+    "rng_state",
+];
 
 pub type SkillId = i64;
 
-#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum ValueKind {
     Nil,
     Bool,
@@ -24,7 +45,7 @@ pub struct ContextSnapshot {
     pub stack_hash: u64,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct SkillPattern {
     pub expected_types: [Option<ValueKind>; 3],
     pub expected_data_bits: u64,
@@ -32,13 +53,13 @@ pub struct SkillPattern {
     pub confidence: f64,
 }
 
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct TaskStats {
     pub attempts: u64,
     pub successes: u64,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct SkillStats {
     pub attempts: u64,
     pub successes: u64,
@@ -61,20 +82,20 @@ impl Default for SkillStats {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct FailureSignature {
     pub stack_hash: u64,
     pub task_tag: Option<u64>,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct PendingCredit {
     pub skill_id: SkillId,
     pub issued_at_tick: u64,
     pub reward_baseline: f64,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct SkillMetadata {
     pub skill_id: SkillId,
     pub pattern: SkillPattern,
@@ -95,7 +116,7 @@ pub struct SkillOutcome {
     pub stack_hash: u64,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct IntuitionWeights {
     pub w_match: f64,
     pub w_success: f64,
@@ -120,6 +141,8 @@ impl Default for IntuitionWeights {
     }
 }
 
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
 pub struct IntuitionEngine {
     pub skill_meta: HashMap<SkillId, SkillMetadata>,
     pub weights: IntuitionWeights,
@@ -144,7 +167,78 @@ impl Default for IntuitionEngine {
     }
 }
 
+#[derive(Serialize, Deserialize)]
+struct IntuitionState<E> {
+    version: u32,
+    engine: E,
+}
+
 impl IntuitionEngine {
+    /// Saves skill metadata, weights, pending credits and the RNG state.
+    pub fn save_to_file<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        self.save_with_options(path, &SaveOptions::default())
+    }
+
+    pub fn save_with_options<P: AsRef<Path>>(
+        &self,
+        path: P,
+        options: &SaveOptions,
+    ) -> io::Result<()> {
+        let state = IntuitionState {
+            version: INTUITION_STATE_VERSION,
+            engine: self,
+        };
+        let bytes = persistence::to_json_checked::<IntuitionState<Self>>(&state)?;
+        persistence::write_atomic(path, &bytes, options)
+    }
+
+    pub fn load_from_file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let bytes = fs::read(path)?;
+        let value: serde_json::Value = serde_json::from_slice(&bytes)?;
+        // Bundles of version 1 stored the bare engine, without an envelope.
+        if value.get("engine").is_none() {
+            if !BARE_ENGINE_FIELDS
+                .iter()
+                .any(|field| value.get(field).is_some())
+            {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "not intuition state: no engine and no engine fields",
+                ));
+            }
+            return Ok(serde_json::from_value(value)?);
+        }
+        let state: IntuitionState<Self> = serde_json::from_value(value)?;
+        if state.version > INTUITION_STATE_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "intuition state version {} is newer than supported version {}",
+                    state.version, INTUITION_STATE_VERSION
+                ),
+            ));
+        }
+        Ok(state.engine)
+    }
+
+    /// Drops metadata and pending credits for skills missing from `skills`,
+    /// returning the removed ids in ascending order.
+    pub fn reconcile_with_library(&mut self, skills: &SkillLibrary) -> Vec<SkillId> {
+        let mut removed: Vec<SkillId> = self
+            .skill_meta
+            .keys()
+            .filter(|id| !skills.macros.contains_key(id))
+            .copied()
+            .collect();
+        removed.sort_unstable();
+        for id in &removed {
+            self.skill_meta.remove(id);
+        }
+        self.pending_credits
+            .retain(|pending| skills.macros.contains_key(&pending.skill_id));
+        removed
+    }
+
     pub fn build_context(
         &self,
         stack: &[UVal],
@@ -187,12 +281,12 @@ impl IntuitionEngine {
 
     pub fn bootstrap_pattern_if_empty(&mut self, skill_id: SkillId, ctx: &ContextSnapshot) {
         self.ensure_skill_known(skill_id);
-        if let Some(meta) = self.skill_meta.get_mut(&skill_id) {
-            if meta.pattern.expected_types == [None, None, None] {
-                meta.pattern.expected_types = ctx.top_types.clone();
-                meta.pattern.expected_data_bits = ctx.feature_hash;
-                meta.pattern.expected_data_mask = u64::MAX;
-            }
+        if let Some(meta) = self.skill_meta.get_mut(&skill_id)
+            && meta.pattern.expected_types == [None, None, None]
+        {
+            meta.pattern.expected_types = ctx.top_types.clone();
+            meta.pattern.expected_data_bits = ctx.feature_hash;
+            meta.pattern.expected_data_mask = u64::MAX;
         }
     }
 
@@ -464,10 +558,10 @@ fn adapt_pattern_failure(
 ) -> SkillPattern {
     let mut next = pattern.clone();
     for (idx, observed_ty) in observed.iter().enumerate() {
-        if let Some(expected) = &next.expected_types[idx] {
-            if Some(expected) != observed_ty.as_ref() {
-                next.expected_types[idx] = None;
-            }
+        if let Some(expected) = &next.expected_types[idx]
+            && Some(expected) != observed_ty.as_ref()
+        {
+            next.expected_types[idx] = None;
         }
     }
     // Keep mask/bits stable on failure; confidence handles down-weighting.
@@ -475,7 +569,7 @@ fn adapt_pattern_failure(
     next
 }
 
-fn value_kind(v: &UVal) -> ValueKind {
+pub fn value_kind(v: &UVal) -> ValueKind {
     match v {
         UVal::Nil => ValueKind::Nil,
         UVal::Bool(_) => ValueKind::Bool,
@@ -486,9 +580,9 @@ fn value_kind(v: &UVal) -> ValueKind {
 }
 
 fn stack_signature_hash(depth: usize, top_types: &[Option<ValueKind>; 3], data_hash: u64) -> u64 {
-    let mut h = 0xcbf29ce484222325u64;
+    let mut h = FNV_OFFSET;
     h ^= depth as u64;
-    h = h.wrapping_mul(0x100000001b3);
+    h = h.wrapping_mul(FNV_PRIME);
 
     for t in top_types {
         let code = match t {
@@ -500,16 +594,16 @@ fn stack_signature_hash(depth: usize, top_types: &[Option<ValueKind>; 3], data_h
             Some(ValueKind::Object) => 5,
         };
         h ^= code;
-        h = h.wrapping_mul(0x100000001b3);
+        h = h.wrapping_mul(FNV_PRIME);
     }
 
     h ^= data_hash;
-    h = h.wrapping_mul(0x100000001b3);
+    h = h.wrapping_mul(FNV_PRIME);
     h
 }
 
 fn scalar_data_hash(stack: &[UVal]) -> u64 {
-    let mut h = 0xcbf29ce484222325u64;
+    let mut h = FNV_OFFSET;
     for v in stack.iter().rev().take(3) {
         let code = match v {
             UVal::Bool(b) => {
@@ -534,7 +628,7 @@ fn scalar_data_hash(stack: &[UVal]) -> u64 {
             UVal::Object(_) => 0x0BEE_u64,
         };
         h ^= code;
-        h = h.wrapping_mul(0x100000001b3);
+        h = h.wrapping_mul(FNV_PRIME);
     }
     h
 }
@@ -1,5 +1,14 @@
+pub mod brain;
+pub mod budget;
+pub mod cfg;
+pub mod constants;
+pub mod dedup;
+pub mod enumerate;
 pub mod evolution;
+pub mod fitness;
+pub mod genetic;
 pub mod memory;
+pub mod persistence;
 pub mod plasticity;
 pub mod types;
 pub mod vm;
@@ -7,10 +16,14 @@ pub mod vm;
 pub mod hypothesis;
 pub mod intuition;
 pub mod logic;
+pub mod optimize;
 pub mod run;
+pub mod strategy;
+pub mod typecheck;
 pub use memory::MemorySystem;
-pub use plasticity::{Event, Plasticity, VMError};
-pub use types::{SkillLibrary, UVal};
+pub use persistence::{BrainFormat, BrainLoadError, SaveOptions};
+pub use plasticity::{Event, Plasticity, PruneConfig, PruneReport, VMError};
+pub use types::{SkillLibrary, SkillNamespace, UVal};
 pub use vm::{Op, SoulGainVM, SKILL_OPCODE_BASE};
 
 pub use logic::{
@@ -1,4 +1,10 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::cfg::analyze_program;
 use crate::plasticity::VMError;
+use crate::types::SkillLibrary;
 use crate::vm::{Op, SKILL_OPCODE_BASE};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -16,6 +22,64 @@ pub enum LogicValidationError {
         needed: usize,
         available: usize,
     },
+    SkillUnderflow {
+        index: usize,
+        skill: i64,
+        needed: usize,
+        available: usize,
+    },
+    InvalidOpcode(i64),
+    UnknownSkill(i64),
+    RecursiveSkill(i64),
+    InvalidJumpTarget {
+        index: usize,
+        target: i64,
+    },
+    JumpIntoOperand {
+        index: usize,
+        target: usize,
+    },
+    ReturnWithoutCall {
+        index: usize,
+    },
+    NoExit {
+        index: usize,
+    },
+}
+
+/// Net stack behaviour of an instruction sequence: it needs `inputs` values
+/// already on the stack and leaves `outputs` values in their place.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub struct StackEffect {
+    pub inputs: usize,
+    pub outputs: usize,
+}
+
+impl StackEffect {
+    pub fn of_op(op: Op) -> Self {
+        let inputs = min_stack_required(op);
+        let outputs = (inputs as isize + logic_of(op).stack_delta).max(0) as usize;
+        Self { inputs, outputs }
+    }
+
+    pub fn net(&self) -> isize {
+        self.outputs as isize - self.inputs as isize
+    }
+
+    /// Effect of running `self` and then `next`.
+    pub fn then(self, next: StackEffect) -> StackEffect {
+        if next.inputs > self.outputs {
+            StackEffect {
+                inputs: self.inputs + (next.inputs - self.outputs),
+                outputs: next.outputs,
+            }
+        } else {
+            StackEffect {
+                inputs: self.inputs,
+                outputs: self.outputs - next.inputs + next.outputs,
+            }
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -76,15 +140,15 @@ pub fn logic_of(op: Op) -> LogicInfo {
             stack_delta: 0,
             may_branch: false,
         },
-        Op::Jmp => LogicInfo {
+        Op::Jmp | Op::JmpRel => LogicInfo {
             stack_delta: 0,
             may_branch: true,
         },
-        Op::JmpIf => LogicInfo {
+        Op::JmpIf | Op::JmpIfRel => LogicInfo {
             stack_delta: -1,
             may_branch: true,
         },
-        Op::Call => LogicInfo {
+        Op::Call | Op::CallRel => LogicInfo {
             stack_delta: 0,
             may_branch: true,
         },
@@ -157,10 +221,18 @@ pub fn logic_of(op: Op) -> LogicInfo {
 
 fn min_stack_required(op: Op) -> usize {
     match op {
-        Op::Literal | Op::Halt | Op::Jmp | Op::Call | Op::Intuition | Op::Reward => 0,
+        Op::Literal
+        | Op::Halt
+        | Op::Jmp
+        | Op::JmpRel
+        | Op::Call
+        | Op::CallRel
+        | Op::Intuition
+        | Op::Reward => 0,
         Op::Load
         | Op::Not
         | Op::JmpIf
+        | Op::JmpIfRel
         | Op::Evolve
         | Op::Dup
         | Op::Drop
@@ -222,13 +294,99 @@ pub fn validate_ops(program: &[Op]) -> Result<(), LogicValidationError> {
     Ok(())
 }
 
+/// Like `validate_ops`, but works on the encoded program: follows every
+/// control-flow path (see `cfg::analyze_program`), accounts for the stack
+/// effect of every skill call and requires some path to reach `Halt`.
+pub fn validate_program(
+    program: &[f64],
+    skills: &SkillLibrary,
+) -> Result<(), LogicValidationError> {
+    let analysis = analyze_program(program, skills, 0)?;
+    if !analysis.reaches_halt {
+        return Err(LogicValidationError::MissingHalt);
+    }
+    Ok(())
+}
+
+/// Stack effect of an encoded program, recursing through nested skills.
+/// `cache` holds effects of skills already resolved and is extended in place.
+pub fn infer_stack_effect(
+    program: &[f64],
+    skills: &SkillLibrary,
+    cache: &mut HashMap<i64, StackEffect>,
+) -> Result<StackEffect, LogicValidationError> {
+    infer_effect_inner(program, skills, cache, &mut Vec::new())
+}
+
+fn infer_effect_inner(
+    program: &[f64],
+    skills: &SkillLibrary,
+    cache: &mut HashMap<i64, StackEffect>,
+    visiting: &mut Vec<i64>,
+) -> Result<StackEffect, LogicValidationError> {
+    let mut total = StackEffect::default();
+    for (_, opcode) in decode_opcodes(program)? {
+        let effect = if opcode >= SKILL_OPCODE_BASE {
+            if let Some(effect) = cache.get(&opcode) {
+                *effect
+            } else {
+                if visiting.contains(&opcode) {
+                    return Err(LogicValidationError::RecursiveSkill(opcode));
+                }
+                let body = skills
+                    .get_skill(opcode)
+                    .ok_or(LogicValidationError::UnknownSkill(opcode))?;
+                visiting.push(opcode);
+                let effect = infer_effect_inner(body, skills, cache, visiting);
+                visiting.pop();
+                let effect = effect?;
+                cache.insert(opcode, effect);
+                effect
+            }
+        } else {
+            let op = Op::from_i64(opcode).ok_or(LogicValidationError::InvalidOpcode(opcode))?;
+            StackEffect::of_op(op)
+        };
+        total = total.then(effect);
+    }
+    Ok(total)
+}
+
+/// `(index, opcode)` for every instruction, with operands skipped.
+pub(crate) fn decode_opcodes(program: &[f64]) -> Result<Vec<(usize, i64)>, LogicValidationError> {
+    instruction_starts(program)
+        .into_iter()
+        .map(|index| {
+            let raw = program[index];
+            if !raw.is_finite() {
+                return Err(LogicValidationError::InvalidOpcode(-1));
+            }
+            let rounded = raw.round();
+            let opcode = rounded as i64;
+            if (rounded - raw).abs() > 1e-9 {
+                return Err(LogicValidationError::InvalidOpcode(opcode));
+            }
+            if Op::from_i64(opcode).is_some_and(has_operand) && index + 1 >= program.len() {
+                return Err(LogicValidationError::InvalidOpcode(opcode));
+            }
+            Ok((index, opcode))
+        })
+        .collect()
+}
+
 pub fn category_of(op: Op) -> OpCategory {
     match op {
         Op::Literal | Op::Dup | Op::Over | Op::Drop | Op::Swap | Op::Parse => OpCategory::Data,
         Op::Store | Op::Load => OpCategory::Memory,
-        Op::Jmp | Op::JmpIf | Op::Call | Op::Ret | Op::Halt | Op::Intuition => {
-            OpCategory::ControlFlow
-        }
+        Op::Jmp
+        | Op::JmpIf
+        | Op::Call
+        | Op::JmpRel
+        | Op::JmpIfRel
+        | Op::CallRel
+        | Op::Ret
+        | Op::Halt
+        | Op::Intuition => OpCategory::ControlFlow,
         Op::Add | Op::Sub | Op::Mul | Op::Mod | Op::Inc | Op::Dec => OpCategory::Arithmetic,
         Op::Eq | Op::Gt | Op::Not | Op::And | Op::Or | Op::Xor | Op::IsZero => OpCategory::Logic,
         Op::Reward | Op::Evolve => OpCategory::Meta,
@@ -251,6 +409,103 @@ pub fn aggregate_trace_logic(trace: &[Op]) -> TraceLogicSummary {
     }
 }
 
+/// Opcodes whose next program word is an inline operand rather than an opcode.
+pub fn has_operand(op: Op) -> bool {
+    matches!(op, Op::Literal) || is_jump(op)
+}
+
+/// Opcodes whose operand names another instruction.
+pub fn is_jump(op: Op) -> bool {
+    matches!(
+        op,
+        Op::Jmp | Op::JmpIf | Op::Call | Op::JmpRel | Op::JmpIfRel | Op::CallRel
+    )
+}
+
+pub fn is_relative_jump(op: Op) -> bool {
+    matches!(op, Op::JmpRel | Op::JmpIfRel | Op::CallRel)
+}
+
+/// Program index a jump at `index` with `operand` lands on. Relative jumps
+/// count from the word after their operand. `None` for non-finite operands;
+/// the result may still be out of bounds.
+pub fn jump_target(op: Op, index: usize, operand: f64) -> Option<i64> {
+    if !operand.is_finite() {
+        return None;
+    }
+    let operand = operand.round() as i64;
+    Some(if is_relative_jump(op) {
+        index as i64 + 2 + operand
+    } else {
+        operand
+    })
+}
+
+/// Operand that makes a jump at `index` land on `target`.
+pub fn jump_operand(op: Op, index: usize, target: usize) -> f64 {
+    if is_relative_jump(op) {
+        target as f64 - (index + 2) as f64
+    } else {
+        target as f64
+    }
+}
+
+/// Absolute targets of every jump in `program` whose operand is finite.
+pub fn jump_targets(program: &[f64]) -> Vec<i64> {
+    instruction_starts(program)
+        .into_iter()
+        .filter_map(|index| {
+            let op = Op::from_i64(program[index].round() as i64).filter(|op| is_jump(*op))?;
+            jump_target(op, index, *program.get(index + 1)?)
+        })
+        .collect()
+}
+
+/// Indices of every opcode word in `program`, skipping inline operands.
+/// Undecodable words are treated as single-word instructions.
+pub fn instruction_starts(program: &[f64]) -> Vec<usize> {
+    let mut starts = Vec::with_capacity(program.len());
+    let mut ip = 0usize;
+    while ip < program.len() {
+        starts.push(ip);
+        let raw = program[ip];
+        ip += 1;
+        if raw.is_finite()
+            && let Some(op) = Op::from_i64(raw.round() as i64)
+            && has_operand(op)
+        {
+            ip += 1;
+        }
+    }
+    starts
+}
+
+/// Skill ids invoked directly by `program`, in order of appearance.
+pub fn skill_calls(program: &[f64]) -> Vec<i64> {
+    instruction_starts(program)
+        .into_iter()
+        .map(|idx| program[idx])
+        .filter(|raw| raw.is_finite() && *raw >= SKILL_OPCODE_BASE as f64)
+        .map(|raw| raw.round() as i64)
+        .collect()
+}
+
+/// Copy of `program` with every skill call found in `remap` redirected.
+/// Operand words are never touched, even if they look like skill ids.
+pub fn rewrite_skill_calls(program: &[f64], remap: &HashMap<i64, i64>) -> Vec<f64> {
+    let mut rewritten = program.to_vec();
+    for idx in instruction_starts(program) {
+        let raw = program[idx];
+        if raw.is_finite()
+            && raw >= SKILL_OPCODE_BASE as f64
+            && let Some(new_id) = remap.get(&(raw.round() as i64))
+        {
+            rewritten[idx] = *new_id as f64;
+        }
+    }
+    rewritten
+}
+
 pub fn decode_ops_for_validation(program: &[f64]) -> Result<Vec<Op>, VMError> {
     let mut ops = Vec::new();
     let mut ip = 0usize;
@@ -278,7 +533,7 @@ pub fn decode_ops_for_validation(program: &[f64]) -> Result<Vec<Op>, VMError> {
 
         ops.push(op);
 
-        if matches!(op, Op::Literal | Op::Jmp | Op::JmpIf | Op::Call) {
+        if has_operand(op) {
             if ip >= program.len() {
                 return Err(VMError::InvalidOpcode(opcode));
             }
@@ -320,5 +575,8 @@ pub fn all_ops() -> &'static [Op] {
         Op::Inc,
         Op::Dec,
         Op::Parse,
+        Op::JmpRel,
+        Op::JmpIfRel,
+        Op::CallRel,
     ]
 }
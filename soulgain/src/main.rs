@@ -1,3 +1,4 @@
+use soulgain::persistence::SaveOptions;
 use soulgain::{run, SoulGainVM};
 
 const BRAIN_PATH: &str = "brain_test.json";
@@ -8,11 +9,29 @@ fn main() {
     println!("==============================================");
 
     let mut vm = SoulGainVM::new(vec![]);
-
-    if vm.plasticity.load_from_file(BRAIN_PATH).is_ok() {
-        println!("[System] Loaded evolved weights from {}", BRAIN_PATH);
-    } else {
-        println!("[System] No brain file found. Initializing tabula rasa.");
+    let save_options = SaveOptions {
+        overwrite_unreadable: std::env::args().any(|arg| arg == "--overwrite-corrupt"),
+        ..SaveOptions::default()
+    };
+
+    match vm.plasticity.load_checked(BRAIN_PATH) {
+        Ok(()) => println!("[System] Loaded evolved weights from {}", BRAIN_PATH),
+        Err(err) if err.is_not_found() => {
+            println!("[System] No brain file found. Initializing tabula rasa.");
+        }
+        Err(err) => {
+            eprintln!("[Error] Brain file {} is corrupted: {}", BRAIN_PATH, err);
+            match vm.plasticity.salvage_from_file(BRAIN_PATH) {
+                Ok(report) => println!(
+                    "[System] Salvaged {} synapses ({} damaged entries skipped)",
+                    report.recovered, report.skipped
+                ),
+                Err(err) => eprintln!("[Error] Salvage failed: {}", err),
+            }
+            if !save_options.overwrite_unreadable {
+                println!("[System] The damaged file will be kept; pass --overwrite-corrupt to replace it.");
+            }
+        }
     }
 
     run::test_numeric_logic(&mut vm);
@@ -26,12 +45,17 @@ fn main() {
 
     println!("\n[System] All tests completed.");
 
-    if let Ok(mem) = vm.plasticity.memory.read() {
-        let synapse_count: usize = mem.weights.values().map(|outgoing| outgoing.len()).sum();
-        println!("[System] Final Synaptic Count: {}", synapse_count);
-    }
-
-    if let Err(err) = vm.plasticity.save_to_file(BRAIN_PATH) {
+    let pruned = vm.plasticity.prune();
+    println!(
+        "[System] Pruned {} synapses ({} weak, {} over fan-out, {} over budget)",
+        pruned.removed(),
+        pruned.below_threshold,
+        pruned.over_fan_out,
+        pruned.over_budget
+    );
+    println!("[System] Final Synaptic Count: {}", pruned.remaining);
+
+    if let Err(err) = vm.plasticity.save_with_options(BRAIN_PATH, &save_options) {
         eprintln!("[Error] Failed to save evolved weights: {}", err);
     } else {
         println!("[System] Brain successfully saved to {}.", BRAIN_PATH);
@@ -1,15 +1,22 @@
 use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
 use crate::types::UVal; // Import UVal so we can store complex types
 
 /// Precision for floating point addresses (1e10).
 const PRECISION_SCALE: f64 = 10_000_000_000.0;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MemorySystem {
     // UPDATED: Now stores UVal instead of just f64
     storage: HashMap<i64, UVal>,
 }
 
+impl Default for MemorySystem {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl MemorySystem {
     pub fn new() -> Self {
         Self {
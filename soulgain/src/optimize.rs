@@ -0,0 +1,373 @@
+use std::collections::HashMap;
+use std::ops::Range;
+
+use crate::logic::{decode_opcodes, has_operand, is_jump, jump_operand, jump_target};
+use crate::types::SkillLibrary;
+use crate::vm::{Op, SKILL_OPCODE_BASE};
+
+/// One instruction with its jump target resolved to an instruction index, so
+/// code can be spliced or deleted without breaking control flow. A target
+/// equal to the number of nodes means "just past the end".
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Node {
+    opcode: f64,
+    operand: Option<f64>,
+    target: Option<usize>,
+}
+
+impl Node {
+    fn op(&self) -> Option<Op> {
+        Op::from_i64(self.opcode as i64)
+    }
+
+    fn skill(&self) -> Option<i64> {
+        let id = self.opcode as i64;
+        (id >= SKILL_OPCODE_BASE).then_some(id)
+    }
+
+    /// Words the instruction occupies once encoded.
+    fn width(&self) -> usize {
+        1 + self.operand.is_some() as usize
+    }
+
+    fn plain(op: Op) -> Self {
+        Node {
+            opcode: op.as_f64(),
+            operand: None,
+            target: None,
+        }
+    }
+}
+
+/// Decodes `program`, or `None` if it has malformed opcodes or jumps that do
+/// not land on an instruction (those are left for the VM to report).
+fn to_nodes(program: &[f64]) -> Option<Vec<Node>> {
+    let decoded = decode_opcodes(program).ok()?;
+    let mut position: HashMap<usize, usize> = decoded
+        .iter()
+        .enumerate()
+        .map(|(pos, (index, _))| (*index, pos))
+        .collect();
+    position.insert(program.len(), decoded.len());
+
+    decoded
+        .iter()
+        .map(|&(index, opcode)| {
+            let op = Op::from_i64(opcode);
+            let operand = op.filter(|op| has_operand(*op)).map(|_| program[index + 1]);
+            let target = match op {
+                Some(op) if is_jump(op) => {
+                    let target = jump_target(op, index, operand?)?;
+                    Some(*position.get(&usize::try_from(target).ok()?)?)
+                }
+                _ => None,
+            };
+            Some(Node {
+                opcode: opcode as f64,
+                operand,
+                target,
+            })
+        })
+        .collect()
+}
+
+fn encode(nodes: &[Node]) -> Vec<f64> {
+    let mut starts = Vec::with_capacity(nodes.len() + 1);
+    let mut len = 0;
+    for node in nodes {
+        starts.push(len);
+        len += node.width();
+    }
+    starts.push(len);
+
+    let mut program = Vec::with_capacity(len);
+    for (pos, node) in nodes.iter().enumerate() {
+        program.push(node.opcode);
+        match (node.target, node.operand, node.op()) {
+            (Some(target), _, Some(op)) => {
+                program.push(jump_operand(op, starts[pos], starts[target]))
+            }
+            (_, Some(operand), _) => program.push(operand),
+            _ => {}
+        }
+    }
+    program
+}
+
+/// Code that observes its own frame (`Call`/`Ret` use the frame's
+/// ip, `Evolve` captures the running program) must stay calls.
+fn frame_independent(body: &[Node]) -> bool {
+    !body.iter().any(|node| {
+        matches!(
+            node.op(),
+            Some(Op::Call | Op::CallRel | Op::Ret | Op::Evolve)
+        )
+    })
+}
+
+fn inline_nodes(
+    nodes: &[Node],
+    skills: &SkillLibrary,
+    visiting: &mut Vec<i64>,
+    as_body: bool,
+) -> Vec<Node> {
+    // Expand each instruction on its own first, so the start of every
+    // original instruction in the output is known before targets are fixed.
+    let mut pieces: Vec<(Vec<Node>, bool)> = Vec::with_capacity(nodes.len());
+    for (pos, node) in nodes.iter().enumerate() {
+        let expanded = node.skill().and_then(|id| {
+            if visiting.contains(&id) {
+                return None;
+            }
+            let body = to_nodes(skills.get_skill(id)?)?;
+            if !frame_independent(&body) {
+                return None;
+            }
+            visiting.push(id);
+            let flat = inline_nodes(&body, skills, visiting, true);
+            visiting.pop();
+            Some(flat)
+        });
+        let piece = match expanded {
+            Some(body) => (body, true),
+            // Inside a skill `Halt` only returns to the caller.
+            None if as_body && node.op() == Some(Op::Halt) => {
+                if pos + 1 == nodes.len() {
+                    (Vec::new(), false)
+                } else {
+                    let mut jump = Node::plain(Op::JmpRel);
+                    jump.operand = Some(0.0);
+                    jump.target = Some(nodes.len());
+                    (vec![jump], false)
+                }
+            }
+            None => (vec![*node], false),
+        };
+        pieces.push(piece);
+    }
+
+    let mut starts = Vec::with_capacity(nodes.len() + 1);
+    let mut total = 0;
+    for (piece, _) in &pieces {
+        starts.push(total);
+        total += piece.len();
+    }
+    starts.push(total);
+
+    let mut out = Vec::with_capacity(total);
+    for (pos, (piece, is_body)) in pieces.into_iter().enumerate() {
+        for mut node in piece {
+            node.target = node.target.map(|target| {
+                if is_body {
+                    starts[pos] + target
+                } else {
+                    starts[target]
+                }
+            });
+            out.push(node);
+        }
+    }
+    out
+}
+
+/// Replaces every skill call with the skill's body, recursively, remapping
+/// jumps in both the caller and the inlined code. `Halt` inside a body
+/// becomes a jump past it. Skills that cannot be inlined safely (unknown,
+/// recursive, or using `Call`/`Ret`/`Evolve`) are left as calls, and a
+/// program that cannot be decoded is returned unchanged.
+pub fn inline_skills(program: &[f64], skills: &SkillLibrary) -> Vec<f64> {
+    let Some(nodes) = to_nodes(program) else {
+        return program.to_vec();
+    };
+    let mut flat = inline_nodes(&nodes, skills, &mut Vec::new(), false);
+    // A body's trailing jump may now point just past the program; give it
+    // somewhere valid to land.
+    if flat.iter().any(|node| node.target == Some(flat.len())) {
+        flat.push(Node::plain(Op::Halt));
+    }
+    encode(&flat)
+}
+
+/// A run of instructions cut out of a program by `extract_window`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Extraction {
+    /// The window as a standalone skill body, with its jumps rebased.
+    pub body: Vec<f64>,
+    /// The program with the window replaced by a single skill call.
+    pub program: Vec<f64>,
+    /// Index of the call in `program`. It holds `SKILL_OPCODE_BASE` as a
+    /// placeholder until the caller knows the skill's id.
+    pub call_index: usize,
+}
+
+/// Cuts the instructions at positions `range` (instructions, not words) out
+/// of `program` so they can become a skill. Fails unless the window is
+/// self-contained: jumps from outside may only land on its first instruction,
+/// jumps inside may only land inside it or right after it, and it may not
+/// `Halt` or use `Call`/`Ret`/`Evolve`. Jumps on both sides are relocated.
+pub fn extract_window(program: &[f64], range: Range<usize>) -> Option<Extraction> {
+    let nodes = to_nodes(program)?;
+    let (start, end) = (range.start, range.end);
+    if start >= end || end > nodes.len() {
+        return None;
+    }
+    let window = &nodes[start..end];
+    if !frame_independent(window) || window.iter().any(|node| node.op() == Some(Op::Halt)) {
+        return None;
+    }
+    for (pos, node) in nodes.iter().enumerate() {
+        let Some(target) = node.target else {
+            continue;
+        };
+        let allowed = if range.contains(&pos) {
+            (start..=end).contains(&target)
+        } else {
+            !(start + 1..end).contains(&target)
+        };
+        if !allowed {
+            return None;
+        }
+    }
+
+    let mut body: Vec<Node> = window
+        .iter()
+        .map(|node| Node {
+            target: node.target.map(|target| target - start),
+            ..*node
+        })
+        .collect();
+    // A jump past the window must land somewhere inside the skill; the
+    // trailing `Halt` returns to the caller, where the window used to end.
+    if body.iter().any(|node| node.target == Some(body.len())) {
+        body.push(Node::plain(Op::Halt));
+    }
+
+    let shift = |target: usize| {
+        if target >= end {
+            target - (end - start) + 1
+        } else {
+            target
+        }
+    };
+    let relocate = |node: &Node| Node {
+        target: node.target.map(shift),
+        ..*node
+    };
+    let mut rest: Vec<Node> = nodes[..start].iter().map(relocate).collect();
+    let call_index = rest.iter().map(Node::width).sum();
+    rest.push(Node {
+        opcode: SKILL_OPCODE_BASE as f64,
+        operand: None,
+        target: None,
+    });
+    rest.extend(nodes[end..].iter().map(relocate));
+
+    Some(Extraction {
+        body: encode(&body),
+        program: encode(&rest),
+        call_index,
+    })
+}
+
+fn fold(op: Op, a: f64, b: f64) -> Option<f64> {
+    match op {
+        Op::Add => Some(a + b),
+        Op::Sub => Some(a - b),
+        Op::Mul => Some(a * b),
+        _ => None,
+    }
+}
+
+/// Matches one rewrite at the start of `window` (consecutive nodes). Returns
+/// how many leading nodes to drop and an optional replacement for the first.
+fn match_pattern(window: &[Node]) -> Option<(usize, Option<Node>)> {
+    let ops: Vec<Option<Op>> = window.iter().map(Node::op).collect();
+    match ops.as_slice() {
+        [Some(Op::Literal), Some(Op::Literal), Some(op), ..] => {
+            let value = fold(*op, window[0].operand?, window[1].operand?)?;
+            let mut folded = window[0];
+            folded.operand = Some(value);
+            Some((3, Some(folded)))
+        }
+        [Some(Op::Swap), Some(Op::Swap), ..]
+        | [Some(Op::Inc), Some(Op::Dec), ..]
+        | [Some(Op::Dec), Some(Op::Inc), ..]
+        | [Some(Op::Dup), Some(Op::Drop), ..]
+        | [Some(Op::Literal), Some(Op::Drop), ..] => Some((2, None)),
+        _ => None,
+    }
+}
+
+/// Rewrites local patterns that have no net effect or can be computed ahead
+/// of time: `Swap Swap`, `Inc Dec`, `Dec Inc`, `Dup Drop` and `Literal x Drop`
+/// disappear, and `Literal x Literal y Add|Sub|Mul` folds to one `Literal`.
+/// A pattern is only rewritten if no jump lands inside it.
+pub fn peephole(program: &[f64]) -> Vec<f64> {
+    let Some(mut nodes) = to_nodes(program) else {
+        return program.to_vec();
+    };
+
+    loop {
+        let mut is_target = vec![false; nodes.len() + 1];
+        for target in nodes.iter().filter_map(|node| node.target) {
+            is_target[target] = true;
+        }
+
+        let mut keep = vec![true; nodes.len()];
+        let mut i = 0;
+        while i < nodes.len() {
+            let window = &nodes[i..nodes.len().min(i + 3)];
+            match match_pattern(window) {
+                Some((len, replacement)) if !is_target[i + 1..i + len].contains(&true) => {
+                    match replacement {
+                        Some(node) => {
+                            nodes[i] = node;
+                            keep[i + 1..i + len].fill(false);
+                        }
+                        None => keep[i..i + len].fill(false),
+                    }
+                    i += len;
+                }
+                _ => i += 1,
+            }
+        }
+        if keep.iter().all(|k| *k) {
+            break;
+        }
+
+        // A jump to a removed instruction now lands on the next kept one,
+        // which is where the removed code would have fallen through to.
+        let mut remap = vec![0; nodes.len() + 1];
+        let mut next = keep.iter().filter(|k| **k).count();
+        remap[nodes.len()] = next;
+        for i in (0..nodes.len()).rev() {
+            if keep[i] {
+                next -= 1;
+            }
+            remap[i] = next;
+        }
+        nodes = nodes
+            .into_iter()
+            .zip(keep)
+            .filter_map(|(mut node, keep)| {
+                node.target = node.target.map(|target| remap[target]);
+                keep.then_some(node)
+            })
+            .collect();
+    }
+    encode(&nodes)
+}
+
+/// `inline_skills` followed by `peephole`.
+pub fn optimize_program(program: &[f64], skills: &SkillLibrary) -> Vec<f64> {
+    peephole(&inline_skills(program, skills))
+}
+
+/// A copy of `skills` with every body optimized. Metadata is kept, so the
+/// copy can stand in for the original at runtime while the readable library
+/// is what gets trained and saved.
+pub fn optimize_library(skills: &SkillLibrary) -> SkillLibrary {
+    let mut optimized = skills.clone();
+    optimized.rewrite_bodies(|_, body| optimize_program(body, skills));
+    optimized
+}
@@ -0,0 +1,615 @@
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::plasticity::{Event, PersistentMemory, VMError, WeightEntry};
+
+/// Leading bytes of every binary brain file.
+pub const BRAIN_MAGIC: [u8; 8] = *b"SGBRAIN\0";
+/// Current version of the binary brain layout.
+pub const BRAIN_FORMAT_VERSION: u16 = 1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BrainFormat {
+    /// Pretty-printed array of `WeightEntry` objects (legacy layout).
+    Json,
+    /// Versioned binary layout with an event-intern table and checksum.
+    Binary,
+}
+
+impl BrainFormat {
+    /// `.json` files keep the legacy layout; every other path gets the binary one.
+    pub fn for_path<P: AsRef<Path>>(path: P) -> Self {
+        match path.as_ref().extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("json") => BrainFormat::Json,
+            _ => BrainFormat::Binary,
+        }
+    }
+
+    /// Sniffs the format from file contents.
+    pub fn detect(bytes: &[u8]) -> Self {
+        if bytes.starts_with(&BRAIN_MAGIC) {
+            BrainFormat::Binary
+        } else {
+            BrainFormat::Json
+        }
+    }
+}
+
+/// Controls how brain files are replaced on disk.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SaveOptions {
+    /// Number of rotating `.bakN` copies of the previous file to keep.
+    pub backups: usize,
+    /// Replace an existing file even if it cannot be loaded. Without this a
+    /// corrupted brain is left untouched so its entries can still be salvaged.
+    pub overwrite_unreadable: bool,
+}
+
+impl Default for SaveOptions {
+    fn default() -> Self {
+        Self {
+            backups: 3,
+            overwrite_unreadable: false,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum BrainLoadError {
+    Io(io::Error),
+    /// The JSON layout failed to parse; `snippet` is the offending source line.
+    Json {
+        line: usize,
+        column: usize,
+        message: String,
+        snippet: String,
+    },
+    /// The binary layout failed its header, checksum or structure checks.
+    Binary(io::Error),
+}
+
+impl BrainLoadError {
+    pub fn is_not_found(&self) -> bool {
+        matches!(self, BrainLoadError::Io(err) if err.kind() == io::ErrorKind::NotFound)
+    }
+
+    fn from_json(err: serde_json::Error, bytes: &[u8]) -> Self {
+        let snippet = String::from_utf8_lossy(bytes)
+            .lines()
+            .nth(err.line().saturating_sub(1))
+            .map(|line| line.trim().chars().take(80).collect())
+            .unwrap_or_default();
+        BrainLoadError::Json {
+            line: err.line(),
+            column: err.column(),
+            message: err.to_string(),
+            snippet,
+        }
+    }
+}
+
+impl fmt::Display for BrainLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BrainLoadError::Io(err) => write!(f, "{}", err),
+            BrainLoadError::Json {
+                line,
+                column,
+                message,
+                snippet,
+            } => write!(
+                f,
+                "JSON parse error at line {}, column {}: {} (near `{}`)",
+                line, column, message, snippet
+            ),
+            BrainLoadError::Binary(err) => write!(f, "binary brain error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for BrainLoadError {}
+
+impl From<io::Error> for BrainLoadError {
+    fn from(err: io::Error) -> Self {
+        BrainLoadError::Io(err)
+    }
+}
+
+impl From<BrainLoadError> for io::Error {
+    fn from(err: BrainLoadError) -> Self {
+        match err {
+            BrainLoadError::Io(err) => err,
+            other => io::Error::new(io::ErrorKind::InvalidData, other.to_string()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct SalvageReport {
+    pub recovered: usize,
+    /// Candidate entries that were found but could not be decoded.
+    pub skipped: usize,
+    /// Why the strict load failed, if it did.
+    pub error: Option<BrainLoadError>,
+}
+
+/// Strictly decodes a brain file image in either format.
+pub fn decode_brain(bytes: &[u8]) -> Result<Vec<WeightEntry>, BrainLoadError> {
+    match BrainFormat::detect(bytes) {
+        BrainFormat::Binary => decode_weights(bytes).map_err(BrainLoadError::Binary),
+        BrainFormat::Json => serde_json::from_slice::<Vec<WeightEntry>>(bytes)
+            .map_err(|err| BrainLoadError::from_json(err, bytes)),
+    }
+}
+
+/// Recovers every well-formed entry from a damaged brain file image.
+pub fn salvage_brain(bytes: &[u8]) -> (Vec<WeightEntry>, SalvageReport) {
+    match decode_brain(bytes) {
+        Ok(entries) => {
+            let report = SalvageReport {
+                recovered: entries.len(),
+                skipped: 0,
+                error: None,
+            };
+            (entries, report)
+        }
+        Err(error) => {
+            let (entries, skipped) = match BrainFormat::detect(bytes) {
+                BrainFormat::Binary => salvage_binary(bytes),
+                BrainFormat::Json => salvage_json(bytes),
+            };
+            let report = SalvageReport {
+                recovered: entries.len(),
+                skipped,
+                error: Some(error),
+            };
+            (entries, report)
+        }
+    }
+}
+
+/// Checks an existing brain file image without decoding it: the binary
+/// layout's header and checksum, or the brackets around the JSON layout.
+/// Used to decide whether a save may replace the file.
+pub fn verify_brain(bytes: &[u8]) -> Result<(), BrainLoadError> {
+    match BrainFormat::detect(bytes) {
+        BrainFormat::Binary => check_frame(bytes)
+            .map(|_| ())
+            .map_err(BrainLoadError::Binary),
+        BrainFormat::Json => {
+            let text = bytes.trim_ascii();
+            if text.starts_with(b"[") && text.ends_with(b"]") {
+                Ok(())
+            } else {
+                Err(BrainLoadError::Io(invalid_data(
+                    "JSON brain file is not a complete array",
+                )))
+            }
+        }
+    }
+}
+
+/// Writes `bytes` to a temporary sibling, syncs it, rotates backups and then
+/// renames it over `path`, so a crash never leaves a half-written brain.
+pub fn write_atomic<P: AsRef<Path>>(
+    path: P,
+    bytes: &[u8],
+    options: &SaveOptions,
+) -> io::Result<()> {
+    let path = path.as_ref();
+    let tmp_path = temp_path(path);
+    let written = File::create(&tmp_path).and_then(|mut file| {
+        file.write_all(bytes)?;
+        file.sync_all()
+    });
+    if let Err(err) = written {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(err);
+    }
+
+    if options.backups > 0 && path.exists() {
+        for n in (1..options.backups).rev() {
+            let from = backup_path(path, n);
+            if from.exists() {
+                fs::rename(&from, backup_path(path, n + 1))?;
+            }
+        }
+        fs::copy(path, backup_path(path, 1))?;
+    }
+
+    fs::rename(&tmp_path, path)?;
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        // Persist the rename itself; not every platform allows syncing a directory.
+        if let Ok(dir) = File::open(dir) {
+            let _ = dir.sync_all();
+        }
+    }
+    Ok(())
+}
+
+/// Pretty JSON for `value`, checked to read back as a `T`. serde_json writes
+/// NaN and infinities as `null`, which no `f64` accepts, so state holding
+/// one fails to save instead of saving and then failing to load.
+pub(crate) fn to_json_checked<T: DeserializeOwned>(value: &impl Serialize) -> io::Result<Vec<u8>> {
+    let bytes = serde_json::to_vec_pretty(value)?;
+    if let Err(err) = serde_json::from_slice::<T>(&bytes) {
+        return Err(invalid_data(format!(
+            "state does not read back from JSON (non-finite number?): {}",
+            err
+        )));
+    }
+    Ok(bytes)
+}
+
+/// Path of the `n`th rotating backup (`brain.json` -> `brain.json.bak1`).
+pub fn backup_path<P: AsRef<Path>>(path: P, n: usize) -> PathBuf {
+    sibling_path(path.as_ref(), &format!(".bak{}", n))
+}
+
+/// A temporary sibling no other save can be using: concurrent saves, in
+/// this process or another, each get their own.
+pub(crate) fn temp_path(path: &Path) -> PathBuf {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let n = NEXT.fetch_add(1, Ordering::Relaxed);
+    sibling_path(path, &format!(".{}.{}.tmp", std::process::id(), n))
+}
+
+fn sibling_path(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+// Layout (all integers little endian):
+//   magic[8] | version u16 | reserved u16
+//   event_count u32 | events...
+//   entry_count u64 | (from u32, to u32, weight f64)...
+//   checksum u64 (FNV-1a over every preceding byte)
+
+const EVENT_OPCODE: u8 = 0;
+const EVENT_CONTEXT: u8 = 1;
+const EVENT_MEMORY_READ: u8 = 2;
+const EVENT_MEMORY_WRITE: u8 = 3;
+const EVENT_REWARD: u8 = 4;
+const EVENT_ERROR: u8 = 5;
+
+const ERROR_STACK_UNDERFLOW: u8 = 0;
+const ERROR_INVALID_OPCODE: u8 = 1;
+const ERROR_INVALID_JUMP: u8 = 2;
+const ERROR_RETURN_STACK_UNDERFLOW: u8 = 3;
+const ERROR_INVALID_EVOLVE: u8 = 4;
+const ERROR_SKILL_DEPTH_EXCEEDED: u8 = 5;
+
+pub fn encode_weights(entries: &[WeightEntry]) -> Vec<u8> {
+    let mut table: Vec<Event> = Vec::new();
+    let mut index: HashMap<Event, u32> = HashMap::new();
+    let mut intern = |event: Event| -> u32 {
+        *index.entry(event).or_insert_with(|| {
+            table.push(event);
+            (table.len() - 1) as u32
+        })
+    };
+    let pairs: Vec<(u32, u32, f64)> = entries
+        .iter()
+        .map(|entry| (intern(entry.from), intern(entry.to), entry.weight))
+        .collect();
+
+    let mut out = Vec::with_capacity(16 + table.len() * 18 + pairs.len() * 16 + 8);
+    out.extend_from_slice(&BRAIN_MAGIC);
+    out.extend_from_slice(&BRAIN_FORMAT_VERSION.to_le_bytes());
+    out.extend_from_slice(&0u16.to_le_bytes());
+
+    out.extend_from_slice(&(table.len() as u32).to_le_bytes());
+    for event in &table {
+        encode_event(&mut out, event);
+    }
+
+    out.extend_from_slice(&(pairs.len() as u64).to_le_bytes());
+    for (from, to, weight) in &pairs {
+        out.extend_from_slice(&from.to_le_bytes());
+        out.extend_from_slice(&to.to_le_bytes());
+        out.extend_from_slice(&weight.to_le_bytes());
+    }
+
+    let checksum = fnv1a(&out);
+    out.extend_from_slice(&checksum.to_le_bytes());
+    out
+}
+
+fn salvage_json(bytes: &[u8]) -> (Vec<WeightEntry>, usize) {
+    let text = String::from_utf8_lossy(bytes);
+    let mut entries = Vec::new();
+    let mut skipped = 0usize;
+    let mut cursor = 0usize;
+
+    // Every entry starts with `{ "from"`; try to parse one from each such
+    // object start and skip over whatever was consumed on success.
+    while let Some(offset) = text[cursor..].find("\"from\"") {
+        let key_at = cursor + offset;
+        cursor = key_at + 1;
+        let Some(open) = text[..key_at].trim_end().strip_suffix('{').map(str::len) else {
+            continue;
+        };
+        let mut stream =
+            serde_json::Deserializer::from_str(&text[open..]).into_iter::<WeightEntry>();
+        match stream.next() {
+            Some(Ok(entry)) => {
+                entries.push(entry);
+                cursor = open + stream.byte_offset();
+            }
+            _ => skipped += 1,
+        }
+    }
+    (entries, skipped)
+}
+
+fn salvage_binary(bytes: &[u8]) -> (Vec<WeightEntry>, usize) {
+    let mut entries = Vec::new();
+    let mut skipped = 0usize;
+    let mut reader = ByteReader::new(bytes);
+    let header_ok = reader.take(BRAIN_MAGIC.len()).is_ok()
+        && matches!(reader.u16(), Ok(BRAIN_FORMAT_VERSION))
+        && reader.u16().is_ok();
+    if !header_ok {
+        return (entries, skipped);
+    }
+    let Ok(event_count) = reader.u32() else {
+        return (entries, skipped);
+    };
+    let mut table = Vec::new();
+    for _ in 0..event_count {
+        match decode_event(&mut reader) {
+            Ok(event) => table.push(event),
+            // Tags are variable length, so nothing after a bad event is trustworthy.
+            Err(_) => return (entries, skipped),
+        }
+    }
+    let Ok(entry_count) = reader.u64() else {
+        return (entries, skipped);
+    };
+    for _ in 0..entry_count {
+        let (Ok(from), Ok(to), Ok(weight)) = (reader.u32(), reader.u32(), reader.f64()) else {
+            break;
+        };
+        match (table.get(from as usize), table.get(to as usize)) {
+            (Some(from), Some(to)) if weight.is_finite() => entries.push(WeightEntry {
+                from: *from,
+                to: *to,
+                weight,
+            }),
+            _ => skipped += 1,
+        }
+    }
+    (entries, skipped)
+}
+
+/// Checks the checksum, magic and version of a binary image and returns
+/// the checksummed body.
+fn check_frame(bytes: &[u8]) -> io::Result<&[u8]> {
+    if bytes.len() < BRAIN_MAGIC.len() + 8 {
+        return Err(invalid_data("binary brain file is truncated"));
+    }
+    let (body, tail) = bytes.split_at(bytes.len() - 8);
+    let stored = u64::from_le_bytes(tail.try_into().unwrap());
+    let computed = fnv1a(body);
+    if stored != computed {
+        return Err(invalid_data(format!(
+            "binary brain checksum mismatch (stored {:016x}, computed {:016x})",
+            stored, computed
+        )));
+    }
+
+    let mut reader = ByteReader::new(body);
+    if reader.take(BRAIN_MAGIC.len())? != BRAIN_MAGIC {
+        return Err(invalid_data("missing binary brain magic header"));
+    }
+    let version = reader.u16()?;
+    if version != BRAIN_FORMAT_VERSION {
+        return Err(invalid_data(format!(
+            "unsupported binary brain version {} (expected {})",
+            version, BRAIN_FORMAT_VERSION
+        )));
+    }
+    Ok(body)
+}
+
+pub fn decode_weights(bytes: &[u8]) -> io::Result<Vec<WeightEntry>> {
+    let body = check_frame(bytes)?;
+    let mut reader = ByteReader::new(body);
+    reader.take(BRAIN_MAGIC.len())?;
+    let _version = reader.u16()?;
+    let _reserved = reader.u16()?;
+
+    let event_count = reader.u32()? as usize;
+    let mut table = Vec::with_capacity(event_count.min(body.len()));
+    for _ in 0..event_count {
+        table.push(decode_event(&mut reader)?);
+    }
+
+    let entry_count = reader.u64()? as usize;
+    let mut entries = Vec::with_capacity(entry_count.min(body.len() / 16));
+    for _ in 0..entry_count {
+        let from = reader.u32()? as usize;
+        let to = reader.u32()? as usize;
+        let weight = reader.f64()?;
+        let (Some(from), Some(to)) = (table.get(from), table.get(to)) else {
+            return Err(invalid_data(format!(
+                "event index out of range at byte {}",
+                reader.pos
+            )));
+        };
+        entries.push(WeightEntry {
+            from: *from,
+            to: *to,
+            weight,
+        });
+    }
+
+    if reader.pos != body.len() {
+        return Err(invalid_data(format!(
+            "{} trailing bytes after weight table",
+            body.len() - reader.pos
+        )));
+    }
+    Ok(entries)
+}
+
+/// Rewrites a brain file in another format, returning the number of synapses.
+pub fn convert_brain_file<P: AsRef<Path>, Q: AsRef<Path>>(
+    src: P,
+    dst: Q,
+    format: BrainFormat,
+) -> io::Result<usize> {
+    let memory = PersistentMemory::load_from_file(src)?;
+    memory.save_to_file_as(dst, format)?;
+    Ok(memory.synapse_count())
+}
+
+fn encode_event(out: &mut Vec<u8>, event: &Event) {
+    match *event {
+        Event::Opcode {
+            opcode,
+            stack_depth,
+        } => {
+            out.push(EVENT_OPCODE);
+            out.extend_from_slice(&opcode.to_le_bytes());
+            out.extend_from_slice(&(stack_depth as u64).to_le_bytes());
+        }
+        Event::Context(id) => {
+            out.push(EVENT_CONTEXT);
+            out.push(id);
+        }
+        Event::MemoryRead => out.push(EVENT_MEMORY_READ),
+        Event::MemoryWrite => out.push(EVENT_MEMORY_WRITE),
+        Event::Reward(intensity) => {
+            out.push(EVENT_REWARD);
+            out.push(intensity);
+        }
+        Event::Error(error) => {
+            out.push(EVENT_ERROR);
+            let (tag, payload) = match error {
+                VMError::StackUnderflow => (ERROR_STACK_UNDERFLOW, 0),
+                VMError::InvalidOpcode(op) => (ERROR_INVALID_OPCODE, op),
+                VMError::InvalidJump(target) => (ERROR_INVALID_JUMP, target),
+                VMError::ReturnStackUnderflow => (ERROR_RETURN_STACK_UNDERFLOW, 0),
+                VMError::InvalidEvolve(id) => (ERROR_INVALID_EVOLVE, id),
+                VMError::SkillDepthExceeded(id) => (ERROR_SKILL_DEPTH_EXCEEDED, id),
+            };
+            out.push(tag);
+            out.extend_from_slice(&payload.to_le_bytes());
+        }
+    }
+}
+
+fn decode_event(reader: &mut ByteReader<'_>) -> io::Result<Event> {
+    let at = reader.pos;
+    let event = match reader.u8()? {
+        EVENT_OPCODE => Event::Opcode {
+            opcode: reader.i64()?,
+            stack_depth: reader.u64()? as usize,
+        },
+        EVENT_CONTEXT => Event::Context(reader.u8()?),
+        EVENT_MEMORY_READ => Event::MemoryRead,
+        EVENT_MEMORY_WRITE => Event::MemoryWrite,
+        EVENT_REWARD => Event::Reward(reader.u8()?),
+        EVENT_ERROR => {
+            let tag = reader.u8()?;
+            let payload = reader.i64()?;
+            Event::Error(match tag {
+                ERROR_STACK_UNDERFLOW => VMError::StackUnderflow,
+                ERROR_INVALID_OPCODE => VMError::InvalidOpcode(payload),
+                ERROR_INVALID_JUMP => VMError::InvalidJump(payload),
+                ERROR_RETURN_STACK_UNDERFLOW => VMError::ReturnStackUnderflow,
+                ERROR_INVALID_EVOLVE => VMError::InvalidEvolve(payload),
+                ERROR_SKILL_DEPTH_EXCEEDED => VMError::SkillDepthExceeded(payload),
+                other => {
+                    return Err(invalid_data(format!(
+                        "unknown error tag {} at byte {}",
+                        other, at
+                    )));
+                }
+            })
+        }
+        other => {
+            return Err(invalid_data(format!(
+                "unknown event tag {} at byte {}",
+                other, at
+            )));
+        }
+    };
+    Ok(event)
+}
+
+pub(crate) const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+pub(crate) const FNV_PRIME: u64 = 0x100000001b3;
+
+/// 64-bit FNV-1a of `bytes`.
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+    fnv1a_extend(FNV_OFFSET, bytes)
+}
+
+/// Continues an FNV-1a `hash` over more bytes.
+pub(crate) fn fnv1a_extend(mut hash: u64, bytes: &[u8]) -> u64 {
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+    hash
+}
+
+fn invalid_data<E: Into<Box<dyn std::error::Error + Send + Sync>>>(msg: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+struct ByteReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, pos: 0 }
+    }
+
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if self.bytes.len() - self.pos < len {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("binary brain truncated at byte {}", self.pos),
+            ));
+        }
+        let slice = &self.bytes[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(slice)
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn i64(&mut self) -> io::Result<i64> {
+        Ok(i64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn f64(&mut self) -> io::Result<f64> {
+        Ok(f64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+}
@@ -1,12 +1,14 @@
 use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
-use std::fs::{File, OpenOptions};
-use std::io::{self, BufReader, BufWriter};
+use std::fs;
+use std::io;
 use std::path::Path;
 use std::sync::{Arc, RwLock, mpsc};
 use std::thread;
 use std::time::{Duration, Instant};
 
+use crate::persistence::{self, BrainFormat, BrainLoadError, SalvageReport, SaveOptions};
+
 // --- CONSTANTS ---
 const A_PLUS: f64 = 0.1;
 const A_MINUS: f64 = 0.12;
@@ -14,6 +16,8 @@ const TAU: f64 = 0.020;
 const WINDOW_S: f64 = 0.1;
 const NORMALIZATION_CAP: f64 = 5.0;
 const REWARD_BOOST: f64 = 0.5;
+/// Number of processed events between automatic prune passes in the worker.
+const PRUNE_INTERVAL_EVENTS: usize = 4096;
 
 #[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
 pub enum VMError {
@@ -22,6 +26,7 @@ pub enum VMError {
     InvalidJump(i64),
     ReturnStackUnderflow,
     InvalidEvolve(i64),
+    SkillDepthExceeded(i64),
 }
 
 #[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
@@ -39,11 +44,53 @@ pub struct PersistentMemory {
     pub weights: HashMap<Event, HashMap<Event, f64>>,
 }
 
+/// One synapse in the flat on-disk layout shared by the JSON and binary formats.
 #[derive(Clone, Debug, Serialize, Deserialize)]
-struct WeightEntry {
-    from: Event,
-    to: Event,
-    weight: f64,
+pub struct WeightEntry {
+    pub from: Event,
+    pub to: Event,
+    pub weight: f64,
+}
+
+/// Limits that keep the synapse map bounded over long training runs.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PruneConfig {
+    /// Synapses whose absolute weight falls below this are removed.
+    pub min_magnitude: f64,
+    /// Maximum outgoing synapses kept per source event.
+    pub max_fan_out: usize,
+    /// Maximum synapses kept across the whole map.
+    pub max_synapses: usize,
+}
+
+impl Default for PruneConfig {
+    fn default() -> Self {
+        Self {
+            min_magnitude: 1e-4,
+            max_fan_out: 64,
+            max_synapses: 20_000,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PruneReport {
+    pub below_threshold: usize,
+    pub over_fan_out: usize,
+    pub over_budget: usize,
+    pub remaining: usize,
+}
+
+impl PruneReport {
+    pub fn removed(&self) -> usize {
+        self.below_threshold + self.over_fan_out + self.over_budget
+    }
+}
+
+impl Default for PersistentMemory {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl PersistentMemory {
@@ -53,14 +100,91 @@ impl PersistentMemory {
         }
     }
 
-    pub fn save_to_file<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
-        let file = OpenOptions::new()
-            .write(true)
-            .create(true)
-            .truncate(true)
-            .open(path)?;
-        let entries: Vec<WeightEntry> = self
-            .weights
+    pub fn synapse_count(&self) -> usize {
+        self.weights.values().map(|outgoing| outgoing.len()).sum()
+    }
+
+    /// Removes weak synapses, then enforces the per-source fan-out cap and the
+    /// global budget. Both caps evict the synapses with the smallest magnitude,
+    /// since those contribute least to `best_next_event` and op selection.
+    pub fn prune(&mut self, config: &PruneConfig) -> PruneReport {
+        let mut report = PruneReport::default();
+
+        for outgoing in self.weights.values_mut() {
+            let before = outgoing.len();
+            outgoing.retain(|_, w| w.is_finite() && w.abs() >= config.min_magnitude);
+            report.below_threshold += before - outgoing.len();
+
+            if outgoing.len() > config.max_fan_out {
+                let mut ranked: Vec<(Event, f64)> =
+                    outgoing.iter().map(|(to, w)| (*to, w.abs())).collect();
+                ranked.sort_by(|a, b| b.1.total_cmp(&a.1));
+                for (to, _) in ranked.drain(config.max_fan_out..) {
+                    outgoing.remove(&to);
+                    report.over_fan_out += 1;
+                }
+            }
+        }
+
+        let total = self.synapse_count();
+        if total > config.max_synapses {
+            let mut ranked: Vec<(Event, Event, f64)> = self
+                .weights
+                .iter()
+                .flat_map(|(from, outgoing)| outgoing.iter().map(|(to, w)| (*from, *to, w.abs())))
+                .collect();
+            let excess = total - config.max_synapses;
+            ranked.select_nth_unstable_by(excess - 1, |a, b| a.2.total_cmp(&b.2));
+            for (from, to, _) in &ranked[..excess] {
+                if let Some(outgoing) = self.weights.get_mut(from) {
+                    outgoing.remove(to);
+                    report.over_budget += 1;
+                }
+            }
+        }
+
+        self.weights.retain(|_, outgoing| !outgoing.is_empty());
+        report.remaining = self.synapse_count();
+        report
+    }
+
+    /// Moves every synapse touching `Event::Opcode` of a remapped id onto
+    /// the id it maps to, adding weights where both already had one.
+    /// Returns how many synapses were moved.
+    pub fn remap_opcodes(&mut self, remap: &HashMap<i64, i64>) -> usize {
+        let rename = |event: Event| match event {
+            Event::Opcode {
+                opcode,
+                stack_depth,
+            } => remap.get(&opcode).map(|&opcode| Event::Opcode {
+                opcode,
+                stack_depth,
+            }),
+            _ => None,
+        };
+        let mut moved = 0;
+        let mut weights: HashMap<Event, HashMap<Event, f64>> =
+            HashMap::with_capacity(self.weights.len());
+        for (from, outgoing) in self.weights.drain() {
+            let new_from = rename(from);
+            for (to, weight) in outgoing {
+                let new_to = rename(to);
+                if new_from.is_some() || new_to.is_some() {
+                    moved += 1;
+                }
+                *weights
+                    .entry(new_from.unwrap_or(from))
+                    .or_default()
+                    .entry(new_to.unwrap_or(to))
+                    .or_default() += weight;
+            }
+        }
+        self.weights = weights;
+        moved
+    }
+
+    pub fn to_entries(&self) -> Vec<WeightEntry> {
+        self.weights
             .iter()
             .flat_map(|(from, outgoing)| {
                 outgoing.iter().map(|(to, weight)| WeightEntry {
@@ -69,22 +193,81 @@ impl PersistentMemory {
                     weight: *weight,
                 })
             })
-            .collect();
-        serde_json::to_writer_pretty(BufWriter::new(file), &entries)?;
-        Ok(())
+            .collect()
     }
 
-    pub fn load_from_file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
-        let file = File::open(path)?;
-        let entries: Vec<WeightEntry> = serde_json::from_reader(BufReader::new(file))?;
+    pub fn from_entries<I: IntoIterator<Item = WeightEntry>>(entries: I) -> Self {
         let mut weights: HashMap<Event, HashMap<Event, f64>> = HashMap::new();
         for entry in entries {
             weights
                 .entry(entry.from)
-                .or_insert_with(HashMap::new)
+                .or_default()
                 .insert(entry.to, entry.weight);
         }
-        Ok(Self { weights })
+        Self { weights }
+    }
+
+    /// Saves in the format implied by the extension (`.json` or binary).
+    pub fn save_to_file<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let format = BrainFormat::for_path(&path);
+        self.save_to_file_as(path, format)
+    }
+
+    pub fn save_to_file_as<P: AsRef<Path>>(&self, path: P, format: BrainFormat) -> io::Result<()> {
+        self.save_with_options(path, format, &SaveOptions::default())
+    }
+
+    /// Atomically replaces `path`, keeping rotating backups. Refuses to replace
+    /// a file that fails `persistence::verify_brain` unless
+    /// `overwrite_unreadable` is set.
+    pub fn save_with_options<P: AsRef<Path>>(
+        &self,
+        path: P,
+        format: BrainFormat,
+        options: &SaveOptions,
+    ) -> io::Result<()> {
+        let path = path.as_ref();
+        if !options.overwrite_unreadable
+            && path.exists()
+            && let Err(err) = fs::read(path)
+                .map_err(BrainLoadError::from)
+                .and_then(|bytes| persistence::verify_brain(&bytes))
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "refusing to overwrite unreadable brain file {}: {}",
+                    path.display(),
+                    err
+                ),
+            ));
+        }
+
+        let entries = self.to_entries();
+        let bytes = match format {
+            BrainFormat::Json => serde_json::to_vec_pretty(&entries)?,
+            BrainFormat::Binary => persistence::encode_weights(&entries),
+        };
+        persistence::write_atomic(path, &bytes, options)
+    }
+
+    /// Loads either format, detected from the file header.
+    pub fn load_from_file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Self::load_checked(path)?)
+    }
+
+    /// Like `load_from_file`, but keeps the precise parse location on failure.
+    pub fn load_checked<P: AsRef<Path>>(path: P) -> Result<Self, BrainLoadError> {
+        let bytes = fs::read(path)?;
+        let entries = persistence::decode_brain(&bytes)?;
+        Ok(Self::from_entries(entries))
+    }
+
+    /// Recovers every intact entry from a damaged file instead of failing.
+    pub fn salvage_from_file<P: AsRef<Path>>(path: P) -> io::Result<(Self, SalvageReport)> {
+        let bytes = fs::read(path)?;
+        let (entries, report) = persistence::salvage_brain(&bytes);
+        Ok((Self::from_entries(entries), report))
     }
 }
 
@@ -92,6 +275,7 @@ impl PersistentMemory {
 pub struct Plasticity {
     sender: mpsc::Sender<PlasticityMessage>,
     pub memory: Arc<RwLock<PersistentMemory>>,
+    prune_config: Arc<RwLock<PruneConfig>>,
 }
 
 enum PlasticityMessage {
@@ -99,14 +283,23 @@ enum PlasticityMessage {
     Batch(Vec<Event>),
 }
 
+impl Default for Plasticity {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Plasticity {
     pub fn new() -> Self {
         let (tx, rx) = mpsc::channel::<PlasticityMessage>();
         let memory = Arc::new(RwLock::new(PersistentMemory::new()));
         let mem_clone = memory.clone();
+        let prune_config = Arc::new(RwLock::new(PruneConfig::default()));
+        let prune_config_clone = prune_config.clone();
 
         thread::spawn(move || {
             let mut recent_events: Vec<(Event, Instant)> = Vec::new();
+            let mut events_since_prune = 0usize;
 
             // The closure now correctly iterates over recent_events using .iter()
             // In src/plasticity.rs
@@ -170,7 +363,7 @@ impl Plasticity {
                             let weight = mem
                                 .weights
                                 .entry(from)
-                                .or_insert_with(HashMap::new)
+                                .or_default()
                                 .entry(to)
                                 .or_insert(0.0);
                             *weight += delta;
@@ -206,6 +399,7 @@ impl Plasticity {
                 match message {
                     PlasticityMessage::Single(event, time) => {
                         process_event(event, time, &mut recent_events);
+                        events_since_prune += 1;
                     }
                     PlasticityMessage::Batch(events) => {
                         if events.is_empty() {
@@ -213,6 +407,7 @@ impl Plasticity {
                         }
                         let now = Instant::now();
                         let len = events.len();
+                        events_since_prune += len;
 
                         // Spread batch events over the window to simulate sequence
                         let step = if len > 1 {
@@ -233,10 +428,52 @@ impl Plasticity {
                         }
                     }
                 }
+
+                // Periodic capacity management so long runs stay bounded
+                if events_since_prune >= PRUNE_INTERVAL_EVENTS {
+                    events_since_prune = 0;
+                    let config = *prune_config_clone.read().unwrap();
+                    mem_clone.write().unwrap().prune(&config);
+                }
             }
         });
 
-        Self { sender: tx, memory }
+        Self {
+            sender: tx,
+            memory,
+            prune_config,
+        }
+    }
+
+    pub fn prune_config(&self) -> PruneConfig {
+        self.prune_config
+            .read()
+            .map(|config| *config)
+            .unwrap_or_default()
+    }
+
+    /// Sets the limits used by both `prune` and the worker's periodic pass.
+    pub fn set_prune_config(&self, config: PruneConfig) {
+        if let Ok(mut current) = self.prune_config.write() {
+            *current = config;
+        }
+    }
+
+    /// Prunes the synapse map immediately using the current `PruneConfig`.
+    pub fn prune(&self) -> PruneReport {
+        let config = self.prune_config();
+        match self.memory.write() {
+            Ok(mut mem) => mem.prune(&config),
+            Err(_) => PruneReport::default(),
+        }
+    }
+
+    /// See `PersistentMemory::remap_opcodes`.
+    pub fn remap_opcodes(&self, remap: &HashMap<i64, i64>) -> usize {
+        match self.memory.write() {
+            Ok(mut mem) => mem.remap_opcodes(remap),
+            Err(_) => 0,
+        }
     }
 
     pub fn observe(&self, event: Event) {
@@ -269,19 +506,44 @@ impl Plasticity {
     }
 
     pub fn save_to_file<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        self.save_with_options(path, &SaveOptions::default())
+    }
+
+    pub fn save_with_options<P: AsRef<Path>>(
+        &self,
+        path: P,
+        options: &SaveOptions,
+    ) -> io::Result<()> {
         let mem = self
             .memory
             .read()
-            .map_err(|_| io::Error::new(io::ErrorKind::Other, "plasticity lock poisoned"))?;
-        mem.save_to_file(path)
+            .map_err(|_| io::Error::other("plasticity lock poisoned"))?;
+        let format = BrainFormat::for_path(&path);
+        mem.save_with_options(path, format, options)
     }
 
     pub fn load_from_file<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
-        let loaded = PersistentMemory::load_from_file(path)?;
+        Ok(self.load_checked(path)?)
+    }
+
+    pub fn load_checked<P: AsRef<Path>>(&self, path: P) -> Result<(), BrainLoadError> {
+        let loaded = PersistentMemory::load_checked(path)?;
+        self.replace_memory(loaded)?;
+        Ok(())
+    }
+
+    /// Loads whatever survives in a damaged file, replacing the current weights.
+    pub fn salvage_from_file<P: AsRef<Path>>(&self, path: P) -> io::Result<SalvageReport> {
+        let (loaded, report) = PersistentMemory::salvage_from_file(path)?;
+        self.replace_memory(loaded)?;
+        Ok(report)
+    }
+
+    fn replace_memory(&self, loaded: PersistentMemory) -> io::Result<()> {
         let mut mem = self
             .memory
             .write()
-            .map_err(|_| io::Error::new(io::ErrorKind::Other, "plasticity lock poisoned"))?;
+            .map_err(|_| io::Error::other("plasticity lock poisoned"))?;
         *mem = loaded;
         Ok(())
     }
@@ -92,11 +92,9 @@ pub fn test_learning_from_failure(vm: &mut SoulGainVM) {
 
     for (from, outgoing) in &memory.weights {
         for (to, weight) in outgoing {
-            if *weight > 0.01 {
-                if let Event::Error(_) = to {
-                    println!("  [SCAR DETECTED] {:?} leads to {:?} (Strength: {:.4})", from, to, weight);
-                    found_scar = true;
-                }
+            if *weight > 0.01 && let Event::Error(_) = to {
+                println!("  [SCAR DETECTED] {:?} leads to {:?} (Strength: {:.4})", from, to, weight);
+                found_scar = true;
             }
         }
     }
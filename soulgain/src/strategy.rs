@@ -0,0 +1,376 @@
+use rand::Rng;
+
+use crate::constants::{self, HOLE, MAX_FIT_HOLES};
+use crate::evolution::Trainer;
+use crate::hypothesis::Hypothesis;
+use crate::types::UVal;
+use crate::vm::Op;
+
+/// What a strategy can see when it proposes a candidate.
+pub struct SearchContext<'a> {
+    pub examples: &'a [(Vec<UVal>, Vec<UVal>)],
+    /// Length (in words) the search is currently working at.
+    pub target_len: usize,
+    /// Fittest program so far, with its trailing `Halt`, and its fitness.
+    pub best: Option<(&'a [f64], f64)>,
+    /// Problem shape, as observed by plasticity.
+    pub shape: u8,
+}
+
+impl SearchContext<'_> {
+    /// True once some candidate has made measurable progress.
+    pub fn has_clue(&self) -> bool {
+        self.best.is_some_and(|(_, fitness)| fitness > 0.0001)
+    }
+
+    /// Stack depth of the first example's input.
+    pub fn input_depth(&self) -> usize {
+        self.examples.first().map_or(0, |(input, _)| input.len())
+    }
+}
+
+/// One way of proposing candidates for `SearchMode::Stochastic`. Each
+/// attempt, the trainer picks a strategy with probability proportional to
+/// `weight` times its registered weight.
+pub trait SearchStrategy: Send {
+    /// Tag used in statistics, logs and skill provenance.
+    fn name(&self) -> &str;
+
+    /// How much this strategy wants to run right now, relative to the
+    /// others. Zero opts out of this attempt.
+    fn weight(&self, cx: &SearchContext<'_>) -> f64;
+
+    /// A candidate program ending in `Halt`, or `None` if there is nothing
+    /// to propose.
+    fn propose(&mut self, trainer: &mut Trainer, cx: &SearchContext<'_>) -> Option<Vec<f64>>;
+
+    /// A fresh copy for a parallel worker (see `Trainer::with_workers`).
+    /// While any registered strategy returns `None`, stochastic searches
+    /// stay on the calling thread.
+    fn fork(&self) -> Option<Box<dyn SearchStrategy>> {
+        None
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StrategyStats {
+    /// Candidates proposed.
+    pub proposals: u64,
+    /// Candidates that beat the best fitness so far.
+    pub improvements: u64,
+    /// Candidates that were promoted as solutions.
+    pub solutions: u64,
+}
+
+struct Entry {
+    strategy: Box<dyn SearchStrategy>,
+    weight: f64,
+    stats: StrategyStats,
+}
+
+/// The strategies a trainer draws from, with their weights and statistics.
+/// The default registry holds the built-in strategies, weighted to match
+/// the original search.
+pub struct StrategyRegistry {
+    entries: Vec<Entry>,
+}
+
+impl Default for StrategyRegistry {
+    fn default() -> Self {
+        let mut registry = Self::empty();
+        registry.register(Box::new(HypothesisStrategy), 1.0);
+        registry.register(Box::new(SpeculateStrategy), 1.0);
+        registry.register(Box::new(MutateStrategy), 1.0);
+        registry.register(Box::new(ExtendStrategy), 1.0);
+        registry.register(Box::new(RandomBuildStrategy), 1.0);
+        registry
+    }
+}
+
+impl StrategyRegistry {
+    pub fn empty() -> Self {
+        Self {
+            entries: Vec::new(),
+        }
+    }
+
+    /// Adds `strategy`, or replaces (and resets the statistics of) the one
+    /// with the same name.
+    pub fn register(&mut self, strategy: Box<dyn SearchStrategy>, weight: f64) {
+        let entry = Entry {
+            strategy,
+            weight: weight.max(0.0),
+            stats: StrategyStats::default(),
+        };
+        match self.position(entry.strategy.name()) {
+            Some(index) => self.entries[index] = entry,
+            None => self.entries.push(entry),
+        }
+    }
+
+    /// Returns false if no strategy is called `name`.
+    pub fn set_weight(&mut self, name: &str, weight: f64) -> bool {
+        match self.position(name) {
+            Some(index) => {
+                self.entries[index].weight = weight.max(0.0);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn weight(&self, name: &str) -> Option<f64> {
+        self.position(name).map(|index| self.entries[index].weight)
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<Box<dyn SearchStrategy>> {
+        let index = self.position(name)?;
+        Some(self.entries.remove(index).strategy)
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.entries.iter().map(|e| e.strategy.name()).collect()
+    }
+
+    /// Statistics of every strategy, in registration order.
+    pub fn stats(&self) -> Vec<(&str, StrategyStats)> {
+        self.entries
+            .iter()
+            .map(|e| (e.strategy.name(), e.stats))
+            .collect()
+    }
+
+    pub fn reset_stats(&mut self) {
+        for entry in &mut self.entries {
+            entry.stats = StrategyStats::default();
+        }
+    }
+
+    /// Adds statistics gathered by another registry (e.g. a parallel
+    /// worker's) to the strategies of the same name.
+    pub(crate) fn absorb_stats(&mut self, stats: &[(String, StrategyStats)]) {
+        for (name, other) in stats {
+            if let Some(index) = self.position(name) {
+                let own = &mut self.entries[index].stats;
+                own.proposals += other.proposals;
+                own.improvements += other.improvements;
+                own.solutions += other.solutions;
+            }
+        }
+    }
+
+    /// Copy for a parallel worker: every strategy forked, with the same
+    /// weights and fresh statistics. `None` if some strategy cannot fork.
+    pub(crate) fn fork(&self) -> Option<Self> {
+        let entries = self
+            .entries
+            .iter()
+            .map(|e| {
+                Some(Entry {
+                    strategy: e.strategy.fork()?,
+                    weight: e.weight,
+                    stats: StrategyStats::default(),
+                })
+            })
+            .collect::<Option<Vec<_>>>()?;
+        Some(Self { entries })
+    }
+
+    fn position(&self, name: &str) -> Option<usize> {
+        self.entries.iter().position(|e| e.strategy.name() == name)
+    }
+
+    /// Picks a strategy at random by weight. `None` if every weight is zero.
+    pub(crate) fn choose<R: Rng + ?Sized>(
+        &self,
+        cx: &SearchContext<'_>,
+        rng: &mut R,
+    ) -> Option<usize> {
+        let weights: Vec<f64> = self
+            .entries
+            .iter()
+            .map(|e| e.weight * e.strategy.weight(cx).max(0.0))
+            .collect();
+        let total: f64 = weights.iter().sum();
+        if total <= 0.0 {
+            return None;
+        }
+        let mut r = rng.r#gen::<f64>() * total;
+        for (index, weight) in weights.iter().enumerate() {
+            if r < *weight {
+                return Some(index);
+            }
+            r -= weight;
+        }
+        weights.iter().rposition(|w| *w > 0.0)
+    }
+
+    pub(crate) fn name(&self, index: usize) -> &str {
+        self.entries[index].strategy.name()
+    }
+
+    pub(crate) fn propose(
+        &mut self,
+        index: usize,
+        trainer: &mut Trainer,
+        cx: &SearchContext<'_>,
+    ) -> Option<Vec<f64>> {
+        let entry = &mut self.entries[index];
+        let program = entry.strategy.propose(trainer, cx)?;
+        entry.stats.proposals += 1;
+        Some(program)
+    }
+
+    pub(crate) fn record_improvement(&mut self, index: usize) {
+        self.entries[index].stats.improvements += 1;
+    }
+
+    pub(crate) fn record_solution(&mut self, index: usize) {
+        self.entries[index].stats.solutions += 1;
+    }
+}
+
+/// A fresh `Hypothesis`. Mostly used before anything shows progress.
+pub struct HypothesisStrategy;
+
+impl SearchStrategy for HypothesisStrategy {
+    fn name(&self) -> &str {
+        "HYPOTHESIS"
+    }
+
+    fn fork(&self) -> Option<Box<dyn SearchStrategy>> {
+        Some(Box::new(Self))
+    }
+
+    fn weight(&self, cx: &SearchContext<'_>) -> f64 {
+        // Have a clue? Only guess completely new things 10% of the time.
+        if cx.has_clue() { 0.1 } else { 0.5 }
+    }
+
+    fn propose(&mut self, trainer: &mut Trainer, cx: &SearchContext<'_>) -> Option<Vec<f64>> {
+        let skills = trainer.vm.skills.skills_fitting_depth(cx.input_depth());
+        let mut program = Hypothesis::generate(cx.target_len, &skills).logic;
+        if program.last() != Some(&Op::Halt.as_f64()) {
+            program.push(Op::Halt.as_f64());
+        }
+        Some(program)
+    }
+}
+
+/// Moves a window of the best program into a new (speculative) skill.
+pub struct SpeculateStrategy;
+
+impl SearchStrategy for SpeculateStrategy {
+    fn name(&self) -> &str {
+        "SPEC"
+    }
+
+    fn fork(&self) -> Option<Box<dyn SearchStrategy>> {
+        Some(Box::new(Self))
+    }
+
+    fn weight(&self, cx: &SearchContext<'_>) -> f64 {
+        if cx.has_clue() { 0.3 } else { 0.0 }
+    }
+
+    fn propose(&mut self, trainer: &mut Trainer, cx: &SearchContext<'_>) -> Option<Vec<f64>> {
+        let mut variant = cx.best?.0.to_vec();
+        trainer.speculate_new_skill(&mut variant, 0)?;
+        Some(variant)
+    }
+}
+
+/// Mutates the best program once it is as long as the target length.
+pub struct MutateStrategy;
+
+impl SearchStrategy for MutateStrategy {
+    fn name(&self) -> &str {
+        "MUTATE"
+    }
+
+    fn fork(&self) -> Option<Box<dyn SearchStrategy>> {
+        Some(Box::new(Self))
+    }
+
+    fn weight(&self, cx: &SearchContext<'_>) -> f64 {
+        match cx.best {
+            Some((best, _)) if cx.has_clue() && best.len() >= cx.target_len => 0.6,
+            _ => 0.0,
+        }
+    }
+
+    fn propose(&mut self, trainer: &mut Trainer, cx: &SearchContext<'_>) -> Option<Vec<f64>> {
+        let mut variant = cx.best?.0.to_vec();
+        trainer.mutate_program(&mut variant, 0);
+        Some(variant)
+    }
+}
+
+/// Grows the best program by one op while it is shorter than the target.
+pub struct ExtendStrategy;
+
+impl SearchStrategy for ExtendStrategy {
+    fn name(&self) -> &str {
+        "EXTEND"
+    }
+
+    fn fork(&self) -> Option<Box<dyn SearchStrategy>> {
+        Some(Box::new(Self))
+    }
+
+    fn weight(&self, cx: &SearchContext<'_>) -> f64 {
+        match cx.best {
+            Some((best, _)) if cx.has_clue() && best.len() < cx.target_len => 0.6,
+            _ => 0.0,
+        }
+    }
+
+    fn propose(&mut self, trainer: &mut Trainer, cx: &SearchContext<'_>) -> Option<Vec<f64>> {
+        let mut variant = cx.best?.0.to_vec();
+        if variant.last() == Some(&Op::Halt.as_f64()) {
+            variant.pop();
+        }
+        // Sometimes combine the result with a new constant; the existing
+        // constants are reopened so all of them are fitted together.
+        let mut depth = cx.input_depth();
+        if trainer.rng().gen_bool(0.3) {
+            let mut reopened = variant.clone();
+            constants::reopen(&mut reopened);
+            if constants::holes(&reopened).len() < MAX_FIT_HOLES {
+                variant = reopened;
+                variant.push(Op::Literal.as_f64());
+                variant.push(HOLE);
+                depth += 1;
+            }
+        }
+        let op = trainer.choose_random_op_with_bias(depth);
+        variant.push(op as f64);
+        if op == Op::Literal.as_i64() {
+            variant.push(HOLE);
+        }
+        variant.push(Op::Halt.as_f64());
+        Some(variant)
+    }
+}
+
+/// Builds a program op by op from the plasticity-biased op distribution.
+pub struct RandomBuildStrategy;
+
+impl SearchStrategy for RandomBuildStrategy {
+    fn name(&self) -> &str {
+        "RANDOM"
+    }
+
+    fn fork(&self) -> Option<Box<dyn SearchStrategy>> {
+        Some(Box::new(Self))
+    }
+
+    fn weight(&self, cx: &SearchContext<'_>) -> f64 {
+        if cx.has_clue() { 0.0 } else { 0.5 }
+    }
+
+    fn propose(&mut self, trainer: &mut Trainer, cx: &SearchContext<'_>) -> Option<Vec<f64>> {
+        let input = &cx.examples.first()?.0;
+        Some(trainer.random_build(input, cx.target_len, cx.shape))
+    }
+}
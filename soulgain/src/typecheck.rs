@@ -0,0 +1,404 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+
+use crate::cfg::{ControlFlowGraph, Instruction, Successor, analyze_graph};
+use crate::intuition::{ValueKind, value_kind};
+use crate::logic::LogicValidationError;
+use crate::types::{SkillLibrary, UVal};
+use crate::vm::Op;
+
+/// The set of `ValueKind`s a stack slot may hold at some program point.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct KindSet(u8);
+
+impl KindSet {
+    pub const EMPTY: KindSet = KindSet(0);
+    pub const NIL: KindSet = KindSet(1);
+    pub const BOOL: KindSet = KindSet(1 << 1);
+    pub const NUMBER: KindSet = KindSet(1 << 2);
+    pub const STRING: KindSet = KindSet(1 << 3);
+    pub const OBJECT: KindSet = KindSet(1 << 4);
+    pub const ANY: KindSet = KindSet(0b1_1111);
+
+    pub fn of(kind: &ValueKind) -> Self {
+        match kind {
+            ValueKind::Nil => Self::NIL,
+            ValueKind::Bool => Self::BOOL,
+            ValueKind::Number => Self::NUMBER,
+            ValueKind::String => Self::STRING,
+            ValueKind::Object => Self::OBJECT,
+        }
+    }
+
+    pub fn of_value(value: &UVal) -> Self {
+        Self::of(&value_kind(value))
+    }
+
+    /// Kinds of a concrete stack, bottom first.
+    pub fn of_values(values: &[UVal]) -> Vec<Self> {
+        values.iter().map(Self::of_value).collect()
+    }
+
+    pub fn union(self, other: KindSet) -> Self {
+        KindSet(self.0 | other.0)
+    }
+
+    pub fn intersects(self, other: KindSet) -> bool {
+        self.0 & other.0 != 0
+    }
+
+    pub fn contains(self, other: KindSet) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// True if every kind in `self` is one of `other`'s.
+    pub fn within(self, other: KindSet) -> bool {
+        other.contains(self)
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn kinds(self) -> Vec<ValueKind> {
+        [
+            ValueKind::Nil,
+            ValueKind::Bool,
+            ValueKind::Number,
+            ValueKind::String,
+            ValueKind::Object,
+        ]
+        .into_iter()
+        .filter(|kind| self.contains(Self::of(kind)))
+        .collect()
+    }
+}
+
+impl fmt::Debug for KindSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if *self == Self::ANY {
+            return write!(f, "Any");
+        }
+        let names: Vec<String> = self.kinds().iter().map(|k| format!("{:?}", k)).collect();
+        if names.is_empty() {
+            write!(f, "Never")
+        } else {
+            write!(f, "{}", names.join("|"))
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TypeError {
+    /// The program fails `cfg::analyze_program`.
+    Structure(LogicValidationError),
+    /// No combination of the kinds reaching `index` is accepted by `op`.
+    Mismatch {
+        index: usize,
+        op: Op,
+        found: Vec<KindSet>,
+    },
+    /// A nested skill is ill-typed for the kinds it is called with.
+    InSkill {
+        index: usize,
+        skill: i64,
+        error: Box<TypeError>,
+    },
+}
+
+impl From<LogicValidationError> for TypeError {
+    fn from(err: LogicValidationError) -> Self {
+        TypeError::Structure(err)
+    }
+}
+
+/// Abstract stack: kinds per slot (bottom first), or `Opaque` once
+/// `Intuition` may have run an arbitrary skill.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Slots {
+    Known(Vec<KindSet>),
+    Opaque,
+}
+
+impl Slots {
+    /// Aligns both stacks at the top; slots below the shorter one are dropped.
+    /// The stack after an op that pushes `out` when its operands have the
+    /// kinds it needs and pushes nothing otherwise, as the VM does. `always`
+    /// says the operands cannot have any other kinds.
+    fn push_if(mut stack: Vec<KindSet>, out: KindSet, always: bool) -> Slots {
+        if out.is_empty() {
+            return Slots::Known(stack);
+        }
+        if always {
+            stack.push(out);
+            return Slots::Known(stack);
+        }
+        let skipped = Slots::Known(stack.clone());
+        stack.push(out);
+        Slots::Known(stack).join(&skipped)
+    }
+
+    fn join(&self, other: &Slots) -> Slots {
+        match (self, other) {
+            (Slots::Known(a), Slots::Known(b)) => {
+                let len = a.len().min(b.len());
+                let a = &a[a.len() - len..];
+                let b = &b[b.len() - len..];
+                Slots::Known(a.iter().zip(b).map(|(x, y)| x.union(*y)).collect())
+            }
+            _ => Slots::Opaque,
+        }
+    }
+}
+
+/// Abstractly runs `program` on a stack holding `inputs` (bottom first) and
+/// predicts the kinds left on the stack when it exits, following every
+/// control-flow path and descending into nested skills. Returns `Ok(None)`
+/// when the result depends on what `Intuition` picks at runtime.
+///
+/// An instruction is ill-typed only if none of the kinds that can reach it
+/// are accepted, so a program that type checks may still fail on some inputs.
+pub fn infer_types(
+    program: &[f64],
+    skills: &SkillLibrary,
+    inputs: &[KindSet],
+) -> Result<Option<Vec<KindSet>>, TypeError> {
+    Checker {
+        skills,
+        cache: HashMap::new(),
+        visiting: Vec::new(),
+    }
+    .run(program, inputs)
+}
+
+type CheckResult = Result<Option<Vec<KindSet>>, TypeError>;
+
+struct Checker<'a> {
+    skills: &'a SkillLibrary,
+    cache: HashMap<(i64, Vec<KindSet>), CheckResult>,
+    visiting: Vec<i64>,
+}
+
+impl Checker<'_> {
+    fn run(&mut self, program: &[f64], inputs: &[KindSet]) -> CheckResult {
+        let cfg = ControlFlowGraph::build(program)?;
+        analyze_graph(program, &cfg, self.skills, inputs.len())?;
+        if cfg.instructions.is_empty() {
+            return Ok(Some(inputs.to_vec()));
+        }
+
+        // Kinds only ever widen and stacks only ever shorten at joins, so
+        // this reaches a fixpoint. Errors are only reported afterwards: a
+        // slot that is ill-typed on the first visit may widen later.
+        let mut states: Vec<Option<Slots>> = vec![None; cfg.instructions.len()];
+        states[0] = Some(Slots::Known(inputs.to_vec()));
+        let mut queue = VecDeque::from([0usize]);
+        let mut exit: Option<Slots> = None;
+        while let Some(pos) = queue.pop_front() {
+            let state = states[pos].clone().expect("queued positions have a state");
+            let (out, _) = self.step(&cfg.instructions[pos], state)?;
+            for succ in cfg.successors(pos) {
+                let slot = match *succ {
+                    Successor::Instr(next) => &mut states[next],
+                    Successor::Exit => &mut exit,
+                };
+                let joined = match slot {
+                    Some(old) => old.join(&out),
+                    None => out.clone(),
+                };
+                if slot.as_ref() != Some(&joined) {
+                    *slot = Some(joined);
+                    if let Successor::Instr(next) = *succ {
+                        queue.push_back(next);
+                    }
+                }
+            }
+        }
+
+        for (pos, state) in states.into_iter().enumerate() {
+            if let Some(state) = state
+                && let (_, Some(err)) = self.step(&cfg.instructions[pos], state)?
+            {
+                return Err(err);
+            }
+        }
+
+        Ok(match exit {
+            Some(Slots::Known(kinds)) => Some(kinds),
+            _ => None,
+        })
+    }
+
+    fn run_skill(&mut self, skill: i64, inputs: Vec<KindSet>) -> CheckResult {
+        if let Some(result) = self.cache.get(&(skill, inputs.clone())) {
+            return result.clone();
+        }
+        if self.visiting.contains(&skill) {
+            return Err(LogicValidationError::RecursiveSkill(skill).into());
+        }
+        let body = self
+            .skills
+            .get_skill(skill)
+            .cloned()
+            .ok_or(LogicValidationError::UnknownSkill(skill))?;
+
+        self.visiting.push(skill);
+        let result = self.run(&body, &inputs);
+        self.visiting.pop();
+        self.cache.insert((skill, inputs), result.clone());
+        result
+    }
+
+    /// Transfer function for one instruction. Structural problems are hard
+    /// errors; a type mismatch is returned alongside a best-effort state.
+    fn step(
+        &mut self,
+        instr: &Instruction,
+        state: Slots,
+    ) -> Result<(Slots, Option<TypeError>), TypeError> {
+        let Slots::Known(mut stack) = state else {
+            return Ok((Slots::Opaque, None));
+        };
+        let pop = |stack: &mut Vec<KindSet>| stack.pop().unwrap_or(KindSet::ANY);
+
+        let Some(op) = instr.op() else {
+            let effect = self.skills.try_stack_effect(instr.opcode)?;
+            let split = stack.len().saturating_sub(effect.inputs);
+            let args = stack.split_off(split);
+            return Ok(match self.run_skill(instr.opcode, args) {
+                Ok(Some(outputs)) => {
+                    stack.extend(outputs);
+                    (Slots::Known(stack), None)
+                }
+                Ok(None) => (Slots::Opaque, None),
+                Err(TypeError::Structure(err)) => return Err(err.into()),
+                Err(err) => {
+                    stack.extend(std::iter::repeat_n(KindSet::ANY, effect.outputs));
+                    let err = TypeError::InSkill {
+                        index: instr.index,
+                        skill: instr.opcode,
+                        error: Box::new(err),
+                    };
+                    (Slots::Known(stack), Some(err))
+                }
+            });
+        };
+
+        let mut error = None;
+        let mut require = |ok: bool, found: Vec<KindSet>| {
+            if !ok && error.is_none() {
+                error = Some(TypeError::Mismatch {
+                    index: instr.index,
+                    op,
+                    found,
+                });
+            }
+        };
+
+        match op {
+            Op::Literal => stack.push(KindSet::NUMBER),
+            Op::Add => {
+                let b = pop(&mut stack);
+                let a = pop(&mut stack);
+                let mut out = KindSet::EMPTY;
+                for kind in [KindSet::NUMBER, KindSet::STRING] {
+                    if a.intersects(kind) && b.intersects(kind) {
+                        out = out.union(kind);
+                    }
+                }
+                require(!out.is_empty(), vec![a, b]);
+                let always = [KindSet::NUMBER, KindSet::STRING]
+                    .into_iter()
+                    .any(|kind| a.within(kind) && b.within(kind));
+                return Ok((Slots::push_if(stack, out, always), error));
+            }
+            Op::Sub | Op::Mul | Op::Mod | Op::Gt => {
+                let b = pop(&mut stack);
+                let a = pop(&mut stack);
+                let numeric = a.intersects(KindSet::NUMBER) && b.intersects(KindSet::NUMBER);
+                require(numeric, vec![a, b]);
+                let out = match (numeric, op) {
+                    (false, _) => KindSet::EMPTY,
+                    (true, Op::Gt) => KindSet::BOOL,
+                    (true, _) => KindSet::NUMBER,
+                };
+                let always = a.within(KindSet::NUMBER) && b.within(KindSet::NUMBER);
+                return Ok((Slots::push_if(stack, out, always), error));
+            }
+            Op::Eq | Op::And | Op::Or | Op::Xor => {
+                pop(&mut stack);
+                pop(&mut stack);
+                stack.push(KindSet::BOOL);
+            }
+            Op::Not | Op::IsZero => {
+                pop(&mut stack);
+                stack.push(KindSet::BOOL);
+            }
+            Op::Inc | Op::Dec => {
+                let a = pop(&mut stack);
+                let numeric = a.intersects(KindSet::NUMBER);
+                require(numeric, vec![a]);
+                let out = if numeric {
+                    KindSet::NUMBER
+                } else {
+                    KindSet::EMPTY
+                };
+                return Ok((Slots::push_if(stack, out, a.within(KindSet::NUMBER)), error));
+            }
+            Op::Store => {
+                let value = pop(&mut stack);
+                let addr = pop(&mut stack);
+                require(addr.intersects(KindSet::NUMBER), vec![addr, value]);
+            }
+            Op::Load => {
+                let addr = pop(&mut stack);
+                let numeric = addr.intersects(KindSet::NUMBER);
+                require(numeric, vec![addr]);
+                let out = if numeric {
+                    KindSet::ANY
+                } else {
+                    KindSet::EMPTY
+                };
+                return Ok((
+                    Slots::push_if(stack, out, addr.within(KindSet::NUMBER)),
+                    error,
+                ));
+            }
+            Op::Parse => {
+                let a = pop(&mut stack);
+                let mut out = KindSet::EMPTY;
+                if a.intersects(KindSet::STRING) {
+                    out = out.union(KindSet::NUMBER).union(KindSet::NIL);
+                }
+                if a.intersects(KindSet::NUMBER) {
+                    out = out.union(KindSet::NUMBER);
+                }
+                if a.intersects(KindSet::NIL.union(KindSet::BOOL).union(KindSet::OBJECT)) {
+                    out = out.union(KindSet::NIL);
+                }
+                stack.push(out);
+            }
+            Op::Dup => {
+                let a = pop(&mut stack);
+                stack.extend([a, a]);
+            }
+            Op::Over => {
+                let b = pop(&mut stack);
+                let a = pop(&mut stack);
+                stack.extend([a, b, a]);
+            }
+            Op::Swap => {
+                let b = pop(&mut stack);
+                let a = pop(&mut stack);
+                stack.extend([b, a]);
+            }
+            Op::Drop | Op::JmpIf | Op::JmpIfRel | Op::Evolve => {
+                pop(&mut stack);
+            }
+            Op::Intuition => return Ok((Slots::Opaque, None)),
+            Op::Jmp | Op::JmpRel | Op::Call | Op::CallRel | Op::Ret | Op::Halt | Op::Reward => {}
+        }
+
+        Ok((Slots::Known(stack), error))
+    }
+}
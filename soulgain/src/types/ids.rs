@@ -0,0 +1,90 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::ops::Range;
+
+use crate::persistence::fnv1a;
+use crate::vm::SKILL_OPCODE_BASE;
+
+/// Skill ids kept for built-in skills and libraries from before namespaces.
+/// `define_skill` accepts them, but the allocator never hands them out.
+pub const BUILTIN_SKILL_IDS: Range<i64> = SKILL_OPCODE_BASE..10_000;
+
+/// Each namespace owns a contiguous block of `1 << NAMESPACE_BITS` ids.
+pub const NAMESPACE_BITS: u32 = 32;
+/// Highest namespace index. Programs store opcodes as `f64`, so every id has
+/// to stay below 2^53 to round-trip exactly.
+pub const MAX_NAMESPACE: u32 = (1 << (53 - NAMESPACE_BITS)) - 1;
+
+/// Block of skill ids owned by one library, team or imported pack. Skills
+/// allocated in different namespaces never collide, but two names can hash
+/// to the same namespace (see `named`).
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize,
+)]
+#[serde(transparent)]
+pub struct SkillNamespace(u32);
+
+impl SkillNamespace {
+    /// The namespace of locally trained skills. Its block starts above the
+    /// primitives and `BUILTIN_SKILL_IDS`.
+    pub const LOCAL: SkillNamespace = SkillNamespace(0);
+
+    pub fn new(index: u32) -> Option<Self> {
+        (index <= MAX_NAMESPACE).then_some(Self(index))
+    }
+
+    /// Deterministic namespace for a name such as a team or pack name. Never
+    /// `LOCAL`. Names are hashed into about two million namespaces, so two
+    /// names can share one; `SkillLibrary::merge` then remaps any clashing
+    /// ids, so a clash costs renumbering, not correctness.
+    pub fn named(name: &str) -> Self {
+        Self(1 + (fnv1a(name.as_bytes()) % MAX_NAMESPACE as u64) as u32)
+    }
+
+    pub fn index(self) -> u32 {
+        self.0
+    }
+
+    /// Ids this namespace may allocate.
+    pub fn id_range(self) -> Range<i64> {
+        let start = (self.0 as i64) << NAMESPACE_BITS;
+        let end = (self.0 as i64 + 1) << NAMESPACE_BITS;
+        if self == Self::LOCAL {
+            BUILTIN_SKILL_IDS.end..end
+        } else {
+            start..end
+        }
+    }
+
+    /// Namespace whose block contains `id`, or `None` for primitives,
+    /// built-in ids and ids beyond the last namespace.
+    pub fn of_id(id: i64) -> Option<Self> {
+        if id < BUILTIN_SKILL_IDS.end {
+            return None;
+        }
+        u32::try_from(id >> NAMESPACE_BITS).ok().and_then(Self::new)
+    }
+
+    /// Smallest id in this namespace above every id in `taken` that belongs
+    /// to it, so allocation is deterministic for a given library. `None`
+    /// once the block is exhausted.
+    pub fn next_id<I: IntoIterator<Item = i64>>(self, taken: I) -> Option<i64> {
+        let range = self.id_range();
+        let next = taken
+            .into_iter()
+            .filter(|id| range.contains(id))
+            .max()
+            .map_or(range.start, |highest| highest + 1);
+        range.contains(&next).then_some(next)
+    }
+}
+
+impl fmt::Display for SkillNamespace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if *self == Self::LOCAL {
+            write!(f, "local")
+        } else {
+            write!(f, "ns{}", self.0)
+        }
+    }
+}
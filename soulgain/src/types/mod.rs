@@ -1,5 +1,7 @@
+pub mod ids;
 pub mod value;
 pub mod skills; // Add this line
 
+pub use ids::{SkillNamespace, BUILTIN_SKILL_IDS};
 pub use value::UVal;
-pub use skills::SkillLibrary; // Add this line
\ No newline at end of file
+pub use skills::{MergeError, MergeReport, SkillInfo, SkillLibrary, SkillSignature}; // Add this line
\ No newline at end of file
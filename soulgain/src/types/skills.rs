@@ -1,27 +1,722 @@
-use std::collections::HashMap;
 use serde::{Deserialize, Serialize};
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::brain::unix_now;
+use crate::intuition::{ValueKind, value_kind};
+use crate::logic::{
+    LogicValidationError, StackEffect, has_operand, infer_stack_effect, instruction_starts,
+    jump_targets, rewrite_skill_calls, skill_calls,
+};
+use crate::persistence::{self, SaveOptions};
+use crate::types::{SkillNamespace, UVal};
+use crate::vm::{Op, SKILL_OPCODE_BASE};
 
 #[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(from = "StoredLibrary")]
 pub struct SkillLibrary {
+    /// Skill bodies by id. Code that edits this directly must call
+    /// `refresh_stack_effects` afterwards to bring the caches below in step.
     pub macros: HashMap<i64, Vec<f64>>,
+    /// Human-facing metadata and provenance, keyed like `macros`.
+    #[serde(default)]
+    pub info: HashMap<i64, SkillInfo>,
+    /// Where `allocate_id` and `merge` take new ids from.
+    #[serde(default)]
+    pub namespace: SkillNamespace,
+    /// Highest id ever defined in `namespace`. Allocation starts above it,
+    /// so ids freed by removal or deduplication are never handed out again.
+    #[serde(default)]
+    high_water: i64,
+    /// Cached stack effects, updated for a skill and everything that calls
+    /// it whenever it is (re)defined or removed.
+    #[serde(skip)]
+    effects: HashMap<i64, StackEffect>,
+    /// Skills whose bodies call each id directly.
+    #[serde(skip)]
+    callers: HashMap<i64, BTreeSet<i64>>,
+    #[serde(skip)]
+    by_inputs: DepthIndex,
+}
+
+/// The serialized fields of a `SkillLibrary`; the caches are rebuilt from
+/// them on deserialization.
+#[derive(Deserialize)]
+struct StoredLibrary {
+    macros: HashMap<i64, Vec<f64>>,
+    #[serde(default)]
+    info: HashMap<i64, SkillInfo>,
+    #[serde(default)]
+    namespace: SkillNamespace,
+    #[serde(default)]
+    high_water: i64,
+}
+
+impl From<StoredLibrary> for SkillLibrary {
+    fn from(stored: StoredLibrary) -> Self {
+        let mut library = Self {
+            macros: stored.macros,
+            info: stored.info,
+            namespace: stored.namespace,
+            high_water: stored.high_water,
+            ..Self::default()
+        };
+        library.refresh_stack_effects();
+        library
+    }
+}
+
+/// Skills with a known stack effect, ordered by inputs and then id, so the
+/// ones fitting a given depth form a prefix.
+#[derive(Clone, Debug, Default)]
+struct DepthIndex {
+    inputs: Vec<usize>,
+    ids: Vec<i64>,
+}
+
+impl DepthIndex {
+    fn insert(&mut self, inputs: usize, id: i64) {
+        let lo = self.inputs.partition_point(|n| *n < inputs);
+        let hi = self.inputs.partition_point(|n| *n <= inputs);
+        let at = lo + self.ids[lo..hi].partition_point(|other| *other < id);
+        self.inputs.insert(at, inputs);
+        self.ids.insert(at, id);
+    }
+
+    fn remove(&mut self, id: i64) {
+        if let Some(at) = self.ids.iter().position(|other| *other == id) {
+            self.inputs.remove(at);
+            self.ids.remove(at);
+        }
+    }
+
+    fn fitting(&self, depth: usize) -> &[i64] {
+        &self.ids[..self.inputs.partition_point(|n| *n <= depth)]
+    }
+}
+
+/// Value kinds a skill takes from the stack (bottom to top) and leaves behind.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SkillSignature {
+    pub inputs: Vec<ValueKind>,
+    pub outputs: Vec<ValueKind>,
+}
+
+impl SkillSignature {
+    /// Infers a signature from training examples. Returns `None` when the
+    /// examples disagree on arity or kinds.
+    pub fn from_examples(examples: &[(Vec<UVal>, Vec<UVal>)]) -> Option<Self> {
+        let kinds = |values: &[UVal]| values.iter().map(value_kind).collect::<Vec<_>>();
+        let (first_in, first_out) = examples.first()?;
+        let signature = Self {
+            inputs: kinds(first_in),
+            outputs: kinds(first_out),
+        };
+        examples
+            .iter()
+            .all(|(input, output)| {
+                kinds(input) == signature.inputs && kinds(output) == signature.outputs
+            })
+            .then_some(signature)
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SkillInfo {
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub signature: Option<SkillSignature>,
+    /// Input/output pairs the skill was synthesized from.
+    pub examples: Vec<(Vec<UVal>, Vec<UVal>)>,
+    /// Unix timestamp (seconds) of the first definition.
+    pub created_at: u64,
+    /// Trainer strategy that produced the skill (`HYPOTHESIS`, `SPEC`, ...).
+    pub strategy: Option<String>,
+}
+
+impl SkillInfo {
+    pub fn synthesized(strategy: &str, examples: &[(Vec<UVal>, Vec<UVal>)]) -> Self {
+        Self {
+            signature: SkillSignature::from_examples(examples),
+            examples: examples.to_vec(),
+            created_at: unix_now(),
+            strategy: Some(strategy.to_string()),
+            ..Self::default()
+        }
+    }
+}
+
+/// What `SkillLibrary::merge` did with each incoming skill.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MergeReport {
+    /// Incoming ids that were free here and kept their id.
+    pub added: Vec<i64>,
+    /// Incoming ids already defined here with the same body.
+    pub identical: Vec<i64>,
+    /// Incoming ids that clashed with a different body, mapped to their new id.
+    pub remapped: BTreeMap<i64, i64>,
+    /// Final ids of merged skills whose bodies had calls redirected.
+    pub rewritten: Vec<i64>,
+}
+
+/// Why `SkillLibrary::merge` left the library untouched.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MergeError {
+    /// The namespace ran out of ids for the remapped skills.
+    NamespaceExhausted,
+    /// The merged skills would call themselves along this path, which
+    /// starts and ends at the same id.
+    RecursiveSkill(Vec<i64>),
+}
+
+impl MergeReport {
+    pub fn is_noop(&self) -> bool {
+        self.added.is_empty() && self.remapped.is_empty()
+    }
+
+    /// Where an incoming id ended up (unchanged unless it was remapped).
+    pub fn resolve(&self, id: i64) -> i64 {
+        self.remapped.get(&id).copied().unwrap_or(id)
+    }
 }
 
 impl SkillLibrary {
     pub fn new() -> Self {
         Self {
             macros: HashMap::new(),
+            info: HashMap::new(),
+            namespace: SkillNamespace::LOCAL,
+            high_water: 0,
+            effects: HashMap::new(),
+            callers: HashMap::new(),
+            by_inputs: DepthIndex::default(),
         }
     }
 
-    pub fn define_skill(&mut self, id: i64, mut program: Vec<f64>) {
-        // Strip HALT so skills can be piped together
-        if program.last() == Some(&8.0) {
+    pub fn with_namespace(namespace: SkillNamespace) -> Self {
+        Self {
+            namespace,
+            ..Self::new()
+        }
+    }
+
+    /// Next never-used id in this library's namespace, or `None` once the
+    /// namespace is exhausted. Deterministic: the same library always yields
+    /// the same id.
+    pub fn allocate_id(&self) -> Option<i64> {
+        self.namespace
+            .next_id(self.macros.keys().copied().chain([self.high_water]))
+    }
+
+    /// Defines (or redefines) a skill. Fails with `RecursiveSkill` and leaves
+    /// the library untouched if the body would make `id` reach itself.
+    pub fn define_skill(
+        &mut self,
+        id: i64,
+        mut program: Vec<f64>,
+    ) -> Result<(), LogicValidationError> {
+        // Strip HALT so skills can be piped together, unless a jump lands on
+        // it (it then marks the end of the skill) or it is really an operand.
+        let last = program.len().checked_sub(1);
+        if program.last() == Some(&Op::Halt.as_f64())
+            && instruction_starts(&program).last().copied() == last
+            && !jump_targets(&program).contains(&(program.len() as i64 - 1))
+        {
             program.pop();
         }
-        self.macros.insert(id, program);
+        if self.find_cycle(id, &program).is_some() {
+            return Err(LogicValidationError::RecursiveSkill(id));
+        }
+        self.insert_body(id, program);
+        self.info.entry(id).or_insert_with(|| SkillInfo {
+            created_at: unix_now(),
+            ..SkillInfo::default()
+        });
+        self.update_effects(&[id]);
+        Ok(())
+    }
+
+    pub fn contains(&self, id: i64) -> bool {
+        self.macros.contains_key(&id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.macros.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.macros.is_empty()
+    }
+
+    /// Every defined id, sorted.
+    pub fn ids(&self) -> Vec<i64> {
+        let mut ids: Vec<i64> = self.macros.keys().copied().collect();
+        ids.sort_unstable();
+        ids
+    }
+
+    /// `(id, body)` for every skill, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = (i64, &[f64])> {
+        self.macros.iter().map(|(id, body)| (*id, body.as_slice()))
+    }
+
+    /// Removes every skill for which `keep` is false, with its metadata, and
+    /// returns the removed ids, sorted. Skills calling a removed one are
+    /// left in place.
+    pub fn retain(&mut self, mut keep: impl FnMut(i64) -> bool) -> Vec<i64> {
+        let removed: Vec<i64> = self.ids().into_iter().filter(|id| !keep(*id)).collect();
+        for &id in &removed {
+            if let Some(body) = self.macros.remove(&id) {
+                self.unlink_calls(id, &body);
+            }
+            self.info.remove(&id);
+        }
+        self.update_effects(&removed);
+        removed
+    }
+
+    /// Replaces every body with `rewrite(id, body)`. Meant for rewrites that
+    /// keep the call graph acyclic, such as redirecting or inlining calls.
+    pub fn rewrite_bodies(&mut self, mut rewrite: impl FnMut(i64, &[f64]) -> Vec<f64>) {
+        let mut changed = Vec::new();
+        for id in self.ids() {
+            let body = rewrite(id, &self.macros[&id]);
+            if body != self.macros[&id] {
+                self.insert_body(id, body);
+                changed.push(id);
+            }
+        }
+        self.update_effects(&changed);
+    }
+
+    /// Makes this library hold exactly `other`'s skills. Only skills added,
+    /// changed or removed since the libraries last matched are touched.
+    pub fn sync_from(&mut self, other: &SkillLibrary) {
+        self.retain(|id| other.contains(id));
+        let mut changed = Vec::new();
+        for (id, body) in other.iter() {
+            if self.get_skill(id).map(Vec::as_slice) != Some(body) {
+                self.insert_body(id, body.to_vec());
+                changed.push(id);
+            }
+            if let Some(info) = other.info(id)
+                && self.info(id) != Some(info)
+            {
+                self.info.insert(id, info.clone());
+            }
+        }
+        self.update_effects(&changed);
+    }
+
+    /// Recomputes every cached stack effect from scratch. Skills whose effect
+    /// cannot be determined (unknown or recursive calls) are left out.
+    pub fn refresh_stack_effects(&mut self) {
+        self.callers.clear();
+        let bodies: Vec<(i64, Vec<i64>)> = self
+            .macros
+            .iter()
+            .map(|(id, body)| (*id, skill_calls(body)))
+            .collect();
+        for (id, calls) in bodies {
+            self.link_calls(id, &calls);
+        }
+        self.effects.clear();
+        self.by_inputs = DepthIndex::default();
+        let ids = self.ids();
+        self.update_effects(&ids);
+    }
+
+    /// Stores `body` under `id` and keeps `callers` in step with it.
+    fn insert_body(&mut self, id: i64, body: Vec<f64>) {
+        let calls = skill_calls(&body);
+        if self.namespace.id_range().contains(&id) {
+            self.high_water = self.high_water.max(id);
+        }
+        if let Some(old) = self.macros.insert(id, body) {
+            self.unlink_calls(id, &old);
+        }
+        self.link_calls(id, &calls);
+    }
+
+    fn link_calls(&mut self, id: i64, calls: &[i64]) {
+        for callee in calls {
+            self.callers.entry(*callee).or_default().insert(id);
+        }
+    }
+
+    fn unlink_calls(&mut self, id: i64, body: &[f64]) {
+        for callee in skill_calls(body) {
+            if let Some(users) = self.callers.get_mut(&callee) {
+                users.remove(&id);
+                if users.is_empty() {
+                    self.callers.remove(&callee);
+                }
+            }
+        }
+    }
+
+    /// Recomputes the cached effects of `changed` and of every skill that
+    /// reaches one of them; the rest of the cache is reused.
+    fn update_effects(&mut self, changed: &[i64]) {
+        let mut affected = BTreeSet::new();
+        let mut stack = changed.to_vec();
+        while let Some(id) = stack.pop() {
+            if affected.insert(id)
+                && let Some(users) = self.callers.get(&id)
+            {
+                stack.extend(users.iter().copied());
+            }
+        }
+        let mut cache = std::mem::take(&mut self.effects);
+        for &id in &affected {
+            cache.remove(&id);
+            self.by_inputs.remove(id);
+        }
+        for &id in &affected {
+            if !self.macros.contains_key(&id) {
+                continue;
+            }
+            if let Ok(effect) = infer_stack_effect(&[id as f64], self, &mut cache) {
+                self.by_inputs.insert(effect.inputs, id);
+            }
+        }
+        self.effects = cache;
+    }
+
+    /// Values consumed and produced by a skill, including nested skill calls.
+    pub fn stack_effect(&self, id: i64) -> Option<StackEffect> {
+        self.try_stack_effect(id).ok()
+    }
+
+    pub fn try_stack_effect(&self, id: i64) -> Result<StackEffect, LogicValidationError> {
+        if let Some(effect) = self.effects.get(&id) {
+            return Ok(*effect);
+        }
+        // Not cached: the skill is unknown or malformed.
+        infer_stack_effect(&[id as f64], self, &mut HashMap::new())
+    }
+
+    /// Skills that can run on a stack of `depth` values without underflowing,
+    /// ordered by how many inputs they take and then by id.
+    pub fn skills_fitting_depth(&self, depth: usize) -> Vec<i64> {
+        self.by_inputs.fitting(depth).to_vec()
+    }
+
+    /// Defines a skill and replaces its metadata. A zero `created_at` is
+    /// filled in with the current time.
+    pub fn define_skill_with_info(
+        &mut self,
+        id: i64,
+        program: Vec<f64>,
+        mut info: SkillInfo,
+    ) -> Result<(), LogicValidationError> {
+        if info.created_at == 0 {
+            info.created_at = unix_now();
+        }
+        self.define_skill(id, program)?;
+        self.info.insert(id, info);
+        Ok(())
+    }
+
+    /// Skills called directly by `id`, sorted.
+    pub fn dependencies(&self, id: i64) -> Vec<i64> {
+        let mut deps = self
+            .macros
+            .get(&id)
+            .map(|body| skill_calls(body))
+            .unwrap_or_default();
+        deps.sort_unstable();
+        deps.dedup();
+        deps
+    }
+
+    /// Skills whose bodies call `id` directly, sorted.
+    pub fn dependents(&self, id: i64) -> Vec<i64> {
+        let mut users: Vec<i64> = self
+            .macros
+            .iter()
+            .filter(|(_, body)| skill_calls(body).contains(&id))
+            .map(|(user, _)| *user)
+            .collect();
+        users.sort_unstable();
+        users
+    }
+
+    /// `roots` and every skill they call, directly or through other skills.
+    pub fn reachable_from(&self, roots: &[i64]) -> BTreeSet<i64> {
+        let mut seen = BTreeSet::new();
+        let mut stack: Vec<i64> = roots.to_vec();
+        while let Some(id) = stack.pop() {
+            if self.macros.contains_key(&id) && seen.insert(id) {
+                stack.extend(self.dependencies(id));
+            }
+        }
+        seen
+    }
+
+    /// The call path `id -> ... -> id` that defining `id` as `program` would
+    /// create, if any.
+    pub fn find_cycle(&self, id: i64, program: &[f64]) -> Option<Vec<i64>> {
+        self.find_cycle_with(id, program, &HashMap::new())
+    }
+
+    /// Like `find_cycle`, with the bodies in `staged` taking the place of
+    /// (or adding to) this library's.
+    fn find_cycle_with(
+        &self,
+        id: i64,
+        program: &[f64],
+        staged: &HashMap<i64, Vec<f64>>,
+    ) -> Option<Vec<i64>> {
+        let dependencies = |current: i64| match staged.get(&current) {
+            Some(body) => skill_calls(body),
+            None => self.dependencies(current),
+        };
+        // Depth-first search from the new body's callees back to `id`,
+        // remembering how each skill was first reached.
+        let mut parent: HashMap<i64, i64> = HashMap::new();
+        let mut stack: Vec<i64> = Vec::new();
+        for callee in skill_calls(program) {
+            if let Entry::Vacant(entry) = parent.entry(callee) {
+                entry.insert(id);
+                stack.push(callee);
+            }
+        }
+        while let Some(current) = stack.pop() {
+            if current == id {
+                let mut path = vec![id];
+                let mut step = parent[&id];
+                while step != id {
+                    path.push(step);
+                    step = parent[&step];
+                }
+                path.push(id);
+                path.reverse();
+                return Some(path);
+            }
+            for callee in dependencies(current) {
+                if let Entry::Vacant(entry) = parent.entry(callee) {
+                    entry.insert(current);
+                    stack.push(callee);
+                }
+            }
+        }
+        None
+    }
+
+    /// Skills that were only ever proposed as building blocks (`SPEC`,
+    /// `SMART`). Everything else, including legacy skills without
+    /// provenance, is treated as a root by `collect_garbage`.
+    pub fn is_speculative(&self, id: i64) -> bool {
+        self.info(id)
+            .and_then(|info| info.strategy.as_deref())
+            .is_some_and(|strategy| matches!(strategy, "SPEC" | "SMART"))
+    }
+
+    /// Every skill that is not speculative, sorted.
+    pub fn root_skills(&self) -> Vec<i64> {
+        let mut roots: Vec<i64> = self
+            .macros
+            .keys()
+            .copied()
+            .filter(|id| !self.is_speculative(*id))
+            .collect();
+        roots.sort_unstable();
+        roots
+    }
+
+    /// Removes every skill not reachable from `roots` and returns the removed
+    /// ids, sorted.
+    pub fn collect_garbage(&mut self, roots: &[i64]) -> Vec<i64> {
+        let live = self.reachable_from(roots);
+        self.retain(|id| live.contains(&id))
     }
 
     pub fn get_skill(&self, id: i64) -> Option<&Vec<f64>> {
         self.macros.get(&id)
     }
-}
\ No newline at end of file
+
+    pub fn info(&self, id: i64) -> Option<&SkillInfo> {
+        self.info.get(&id)
+    }
+
+    /// Metadata for a defined skill, created on demand for legacy libraries.
+    pub fn info_mut(&mut self, id: i64) -> Option<&mut SkillInfo> {
+        if !self.macros.contains_key(&id) {
+            return None;
+        }
+        Some(self.info.entry(id).or_default())
+    }
+
+    pub fn set_name(&mut self, id: i64, name: impl Into<String>) -> bool {
+        match self.info_mut(id) {
+            Some(info) => {
+                info.name = Some(name.into());
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn set_description(&mut self, id: i64, description: impl Into<String>) -> bool {
+        match self.info_mut(id) {
+            Some(info) => {
+                info.description = Some(description.into());
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn find_by_name(&self, name: &str) -> Option<i64> {
+        self.info
+            .iter()
+            .find(|(id, info)| info.name.as_deref() == Some(name) && self.macros.contains_key(id))
+            .map(|(id, _)| *id)
+    }
+
+    /// The skill's name if it has one, otherwise a disassembly of its body
+    /// with nested skills shown by name, e.g. `Sub IsZero`.
+    pub fn describe(&self, id: i64) -> String {
+        if let Some(name) = self.info(id).and_then(|info| info.name.clone()) {
+            return name;
+        }
+        match self.macros.get(&id) {
+            Some(body) => self.disassemble(body),
+            None => format!("<unknown skill {}>", id),
+        }
+    }
+
+    pub fn disassemble(&self, program: &[f64]) -> String {
+        let mut words = Vec::new();
+        for idx in instruction_starts(program) {
+            let raw = program[idx];
+            let opcode = raw.round() as i64;
+            let word = if opcode >= SKILL_OPCODE_BASE {
+                match self.info(opcode).and_then(|info| info.name.as_deref()) {
+                    Some(name) => name.to_string(),
+                    None => format!("skill_{}", opcode),
+                }
+            } else {
+                match Op::from_i64(opcode) {
+                    Some(op) if has_operand(op) => match program.get(idx + 1) {
+                        Some(value) => format!("{:?}({})", op, value),
+                        None => format!("{:?}(?)", op),
+                    },
+                    Some(op) => format!("{:?}", op),
+                    None => format!("?{}", raw),
+                }
+            };
+            words.push(word);
+        }
+        words.join(" ")
+    }
+
+    pub fn save_to_file<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        self.save_with_options(path, &SaveOptions::default())
+    }
+
+    pub fn save_with_options<P: AsRef<Path>>(
+        &self,
+        path: P,
+        options: &SaveOptions,
+    ) -> io::Result<()> {
+        let bytes = persistence::to_json_checked::<Self>(self)?;
+        persistence::write_atomic(path, &bytes, options)
+    }
+
+    pub fn load_from_file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let bytes = fs::read(path)?;
+        Ok(serde_json::from_slice(&bytes)?)
+    }
+
+    /// Merges `other` into this library. Incoming skills whose id is taken by
+    /// a different body get a fresh id from this library's namespace (packs
+    /// built in their own namespace never clash), and every incoming body
+    /// that calls a remapped skill is rewritten to call the new id instead.
+    /// Fails, leaving the library untouched, if the namespace runs out of ids
+    /// for the remapped skills or if the merged skills would be recursive,
+    /// e.g. a local 10000 calling 10001 merged with an incoming 10001 that
+    /// calls 10000.
+    pub fn merge(&mut self, other: &SkillLibrary) -> Result<MergeReport, MergeError> {
+        let mut incoming: Vec<i64> = other.macros.keys().copied().collect();
+        incoming.sort_unstable();
+
+        // A body only matches after its own calls are redirected, and each new
+        // clash can change other bodies, so iterate until the remap is stable.
+        let mut remap: HashMap<i64, i64> = HashMap::new();
+        let mut reserved: HashSet<i64> = self.macros.keys().copied().collect();
+        reserved.extend(incoming.iter().copied());
+        reserved.insert(self.high_water);
+        loop {
+            let mut changed = false;
+            for &id in &incoming {
+                if remap.contains_key(&id) {
+                    continue;
+                }
+                let Some(existing) = self.macros.get(&id) else {
+                    continue;
+                };
+                if rewrite_skill_calls(&other.macros[&id], &remap) != *existing {
+                    let new_id = self
+                        .namespace
+                        .next_id(reserved.iter().copied())
+                        .ok_or(MergeError::NamespaceExhausted)?;
+                    reserved.insert(new_id);
+                    remap.insert(id, new_id);
+                    changed = true;
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+
+        let mut report = MergeReport::default();
+        let mut staged: HashMap<i64, Vec<f64>> = HashMap::new();
+        let mut sources = Vec::new();
+        for &id in &incoming {
+            let body = &other.macros[&id];
+            let rewritten = rewrite_skill_calls(body, &remap);
+            let target = remap.get(&id).copied().unwrap_or(id);
+            match remap.get(&id) {
+                Some(&new_id) => {
+                    report.remapped.insert(id, new_id);
+                }
+                None if self.macros.contains_key(&id) => {
+                    report.identical.push(id);
+                    continue;
+                }
+                None => report.added.push(id),
+            }
+            if rewritten != *body {
+                report.rewritten.push(target);
+            }
+            staged.insert(target, rewritten);
+            sources.push((id, target));
+        }
+        for &(_, target) in &sources {
+            if let Some(path) = self.find_cycle_with(target, &staged[&target], &staged) {
+                return Err(MergeError::RecursiveSkill(path));
+            }
+        }
+
+        let mut merged = Vec::new();
+        for (id, target) in sources {
+            self.insert_body(target, staged.remove(&target).expect("staged above"));
+            merged.push(target);
+            let info = other.info.get(&id).cloned().unwrap_or_else(|| SkillInfo {
+                created_at: unix_now(),
+                ..SkillInfo::default()
+            });
+            self.info.insert(target, info);
+        }
+        report.rewritten.sort_unstable();
+        self.update_effects(&merged);
+        Ok(report)
+    }
+}
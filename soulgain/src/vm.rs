@@ -1,13 +1,21 @@
 use std::collections::VecDeque;
+use std::io;
+use std::path::Path;
 use std::sync::Arc;
 
+use crate::brain::{self, BrainManifest, VmCounters};
+use crate::dedup::{DuplicateGroup, find_duplicates, merge_duplicates};
 use crate::intuition::{IntuitionEngine, SkillOutcome, ValueKind};
-use crate::logic::{decode_ops_for_validation, logic_of, validate_ops};
+use crate::logic::{LogicValidationError, jump_target, logic_of, validate_program};
 use crate::memory::MemorySystem;
 use crate::plasticity::{Event, Plasticity, VMError};
-use crate::types::{SkillLibrary, UVal};
+use crate::types::{SkillInfo, SkillLibrary, UVal};
 
 pub const SKILL_OPCODE_BASE: i64 = 1000;
+/// Nested skill invocations allowed before the VM refuses to go deeper.
+/// `define_skill` rejects cycles, but libraries loaded from disk are not
+/// re-checked.
+pub const MAX_SKILL_DEPTH: usize = 256;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(i64)]
@@ -41,6 +49,11 @@ pub enum Op {
     Inc = 27,
     Dec = 28,
     Parse = 29,
+    /// Like `Jmp`/`JmpIf`/`Call`, but the operand is an offset from the end
+    /// of the instruction, so the code can be moved without relocation.
+    JmpRel = 30,
+    JmpIfRel = 31,
+    CallRel = 32,
 }
 
 impl Op {
@@ -75,6 +88,9 @@ impl Op {
             27 => Some(Op::Inc),
             28 => Some(Op::Dec),
             29 => Some(Op::Parse),
+            30 => Some(Op::JmpRel),
+            31 => Some(Op::JmpIfRel),
+            32 => Some(Op::CallRel),
             _ => None,
         }
     }
@@ -152,6 +168,111 @@ impl SoulGainVM {
         self.current_task_tag = task_tag;
     }
 
+    /// Errors recorded since the VM was created.
+    pub fn error_count(&self) -> u64 {
+        self.error_count
+    }
+
+    /// Clears the execution state (program, stacks, unflushed trace) so the
+    /// VM can run an unrelated program. Learned state and counters are kept.
+    pub fn reset_execution(&mut self) {
+        self.program.clear();
+        self.ip = 0;
+        self.stack.clear();
+        self.call_stack.clear();
+        self.program_stack.clear();
+        self.trace.clear();
+        self.recent_opcodes.clear();
+        self.last_event = None;
+    }
+
+    /// Zeroes the tick, reward and error counters, as on a new VM.
+    pub fn reset_counters(&mut self) {
+        self.tick = 0;
+        self.total_reward = 0.0;
+        self.error_count = 0;
+    }
+
+    /// Persists everything the VM has learned (plasticity, skills, intuition,
+    /// memory and execution counters) into the bundle directory `dir`. The
+    /// bundle is assembled next to `dir` and swapped in whole; the one it
+    /// replaces is kept as `dir.bak1`.
+    pub fn save_brain<P: AsRef<Path>>(&self, dir: P) -> io::Result<()> {
+        let dir = dir.as_ref();
+        let staging = brain::staging_dir(dir)?;
+        if let Err(err) = self.write_bundle(&staging) {
+            let _ = std::fs::remove_dir_all(&staging);
+            return Err(err);
+        }
+        brain::commit_staged(&staging, dir)
+    }
+
+    fn write_bundle(&self, dir: &Path) -> io::Result<()> {
+        self.plasticity.save_with_options(
+            dir.join(brain::PLASTICITY_FILE),
+            &brain::bundle_save_options(),
+        )?;
+        self.skills
+            .save_with_options(dir.join(brain::SKILLS_FILE), &brain::bundle_save_options())?;
+        self.intuition.save_with_options(
+            dir.join(brain::INTUITION_FILE),
+            &brain::bundle_save_options(),
+        )?;
+        brain::write_json(&dir.join(brain::MEMORY_FILE), &self.memory)?;
+        let manifest = BrainManifest::new(VmCounters {
+            tick: self.tick,
+            total_reward: self.total_reward,
+            error_count: self.error_count,
+        });
+        brain::write_json(&dir.join(brain::MANIFEST_FILE), &manifest)
+    }
+
+    /// Removes skills unreachable from `roots` (see
+    /// `SkillLibrary::collect_garbage`) along with their intuition metadata.
+    pub fn collect_skill_garbage(&mut self, roots: &[i64]) -> Vec<i64> {
+        let removed = self.skills.collect_garbage(roots);
+        self.intuition.reconcile_with_library(&self.skills);
+        removed
+    }
+
+    /// Merges behaviorally identical skills (see `dedup::merge_duplicates`),
+    /// moves their learned opcode weights onto the surviving ids and drops
+    /// intuition metadata for the removed ids. Returns the groups as merged:
+    /// duplicates that failed the example check are left out.
+    pub fn deduplicate_skills(&mut self) -> Vec<DuplicateGroup> {
+        let mut groups = find_duplicates(&self.skills);
+        let remap = merge_duplicates(&mut self.skills, &groups);
+        self.plasticity.remap_opcodes(&remap);
+        self.intuition.reconcile_with_library(&self.skills);
+        for group in &mut groups {
+            group.duplicates.retain(|id| remap.contains_key(id));
+        }
+        groups.retain(|group| !group.duplicates.is_empty());
+        groups
+    }
+
+    /// Restores a bundle written by `save_brain`. Every component is decoded
+    /// before any VM state changes, so a failed load leaves the VM untouched.
+    pub fn load_brain<P: AsRef<Path>>(&mut self, dir: P) -> io::Result<BrainManifest> {
+        let dir = brain::bundle_dir(dir.as_ref());
+        let dir = dir.as_path();
+        let manifest = brain::read_manifest(dir)?;
+        let skills = SkillLibrary::load_from_file(dir.join(brain::SKILLS_FILE))?;
+        let mut intuition = IntuitionEngine::load_from_file(dir.join(brain::INTUITION_FILE))?;
+        let memory: MemorySystem = brain::read_json(&dir.join(brain::MEMORY_FILE))?;
+        self.plasticity
+            .load_from_file(dir.join(brain::PLASTICITY_FILE))?;
+
+        intuition.reconcile_with_library(&skills);
+        self.skills = skills;
+        self.intuition = intuition;
+        self.memory = memory;
+        self.tick = manifest.counters.tick;
+        self.total_reward = manifest.counters.total_reward;
+        self.error_count = manifest.counters.error_count;
+        Ok(manifest)
+    }
+
     #[inline(always)]
     fn decode_opcode(raw: f64) -> Result<i64, VMError> {
         if !raw.is_finite() {
@@ -258,6 +379,10 @@ impl SoulGainVM {
     }
 
     fn execute_skill(&mut self, opcode: i64) {
+        if self.program_stack.len() >= MAX_SKILL_DEPTH {
+            self.record_error(VMError::SkillDepthExceeded(opcode));
+            return;
+        }
         if let Some(macro_code) = self.skills.get_skill(opcode).cloned() {
             let ctx = self.intuition.build_context(
                 &self.stack,
@@ -415,7 +540,8 @@ impl SoulGainVM {
                 }
             }
             Op::Intuition => {
-                let candidates: Vec<i64> = self.skills.macros.keys().copied().collect();
+                // Only offer skills whose inputs are already on the stack
+                let candidates = self.skills.skills_fitting_depth(self.stack.len());
                 let ctx = self.intuition.build_context(
                     &self.stack,
                     &self.recent_opcodes,
@@ -425,24 +551,19 @@ impl SoulGainVM {
                     self.execute_skill(skill_id);
                 }
             }
-            Op::Jmp => {
+            Op::Jmp | Op::JmpRel => {
                 if self.ip >= self.program.len() {
                     return false;
                 }
-                let target = self.program[self.ip];
-                self.ip += 1;
-                if !target.is_finite() || target < 0.0 {
-                    self.record_error(VMError::InvalidJump(-1));
-                    return true;
-                }
-                let new_ip = target.round() as usize;
-                if new_ip >= self.program.len() {
-                    self.record_error(VMError::InvalidJump(new_ip as i64));
-                    return true;
+                match self.take_jump_target(opcode) {
+                    Ok(new_ip) => self.ip = new_ip,
+                    Err(err) => {
+                        self.record_error(err);
+                        return true;
+                    }
                 }
-                self.ip = new_ip;
             }
-            Op::JmpIf => {
+            Op::JmpIf | Op::JmpIfRel => {
                 if self.ip >= self.program.len() {
                     self.record_error(VMError::InvalidJump(-1));
                     return false;
@@ -451,39 +572,32 @@ impl SoulGainVM {
                     self.record_error(VMError::StackUnderflow);
                     return true;
                 }
-                let target = self.program[self.ip];
-                self.ip += 1;
+                let target = self.take_jump_target(opcode);
                 let condition = self.stack.pop().unwrap();
                 if condition.is_truthy() {
-                    if !target.is_finite() || target < 0.0 {
-                        self.record_error(VMError::InvalidJump(-1));
-                        return true;
-                    }
-                    let new_ip = target.round() as usize;
-                    if new_ip >= self.program.len() {
-                        self.record_error(VMError::InvalidJump(new_ip as i64));
-                        return true;
+                    match target {
+                        Ok(new_ip) => self.ip = new_ip,
+                        Err(err) => {
+                            self.record_error(err);
+                            return true;
+                        }
                     }
-                    self.ip = new_ip;
                 }
             }
-            Op::Call => {
+            Op::Call | Op::CallRel => {
                 if self.ip >= self.program.len() {
                     return false;
                 }
-                let target = self.program[self.ip];
-                self.ip += 1;
-                if !target.is_finite() || target < 0.0 {
-                    self.record_error(VMError::InvalidJump(-1));
-                    return true;
-                }
-                let new_ip = target.round() as usize;
-                if new_ip >= self.program.len() {
-                    self.record_error(VMError::InvalidJump(new_ip as i64));
-                    return true;
+                match self.take_jump_target(opcode) {
+                    Ok(new_ip) => {
+                        self.call_stack.push(self.ip);
+                        self.ip = new_ip;
+                    }
+                    Err(err) => {
+                        self.record_error(err);
+                        return true;
+                    }
                 }
-                self.call_stack.push(self.ip);
-                self.ip = new_ip;
             }
             Op::Ret => {
                 if let Some(return_ip) = self.call_stack.pop() {
@@ -499,12 +613,25 @@ impl SoulGainVM {
             }
             Op::Evolve => {
                 if let Some(UVal::Number(id)) = self.stack.pop() {
+                    // Only ids this library allocates from: the rest belong
+                    // to primitives, built-in skills or other namespaces.
+                    if !self.skills.namespace.id_range().contains(&(id as i64)) {
+                        self.record_error(VMError::InvalidEvolve(id as i64));
+                        return true;
+                    }
                     let skill_program = self.program.clone();
-                    match decode_ops_for_validation(&skill_program).and_then(|ops| {
-                        validate_ops(&ops).map_err(|_| VMError::InvalidEvolve(id as i64))
+                    let defined = validate_program(&skill_program, &self.skills).and_then(|_| {
+                        self.skills.define_skill_with_info(
+                            id as i64,
+                            skill_program,
+                            SkillInfo::synthesized("EVOLVE", &[]),
+                        )
+                    });
+                    match defined.map_err(|err| match err {
+                        LogicValidationError::InvalidOpcode(op) => VMError::InvalidOpcode(op),
+                        _ => VMError::InvalidEvolve(id as i64),
                     }) {
                         Ok(_) => {
-                            self.skills.define_skill(id as i64, skill_program);
                             self.record_event(Event::Reward(100));
                             self.flush_trace();
                         }
@@ -622,13 +749,18 @@ impl SoulGainVM {
         true
     }
 
-    fn find_next_opcode(&self, target_opcode: i64) -> Option<usize> {
-        self.program.iter().enumerate().find_map(|(idx, &raw)| {
-            if raw == target_opcode as f64 {
-                Some(idx)
-            } else {
-                None
+    /// Consumes the operand of the jump `op` and resolves it to an index in
+    /// the current program. `ip` is left just past the operand either way.
+    fn take_jump_target(&mut self, op: Op) -> Result<usize, VMError> {
+        let index = self.ip - 1;
+        let operand = self.program[self.ip];
+        self.ip += 1;
+        match jump_target(op, index, operand) {
+            Some(target) if target >= 0 && (target as usize) < self.program.len() => {
+                Ok(target as usize)
             }
-        })
+            Some(target) if target >= 0 => Err(VMError::InvalidJump(target)),
+            _ => Err(VMError::InvalidJump(-1)),
+        }
     }
 }